@@ -0,0 +1,518 @@
+use crate::{
+    config::{ArchiveDevice, Config},
+    error::{BackyError, BackyResult},
+    manifest::Manifest,
+};
+
+use chrono::NaiveDate;
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::CString,
+    fs, io,
+    mem::MaybeUninit,
+    os::unix::{
+        ffi::OsStrExt,
+        fs::{symlink, MetadataExt, PermissionsExt},
+    },
+    path::{Path, PathBuf},
+};
+
+// #######################
+//   Definições públicas
+// #######################
+/// Formato dos nomes dos diretórios de snapshot dentro do arquivo de backups
+pub const SNAPSHOT_FORMAT: &str = "%Y%m%d";
+
+/// Nome do link simbólico que aponta para o snapshot mais recente
+pub const LATEST_LINK: &str = "latest";
+
+/// Nome do diretório (dentro de cada snapshot) com os metadados do backy
+pub const METADATA_DIR: &str = ".backy";
+
+/// Nome do arquivo que identifica o disco do arquivo de backups
+pub const ARCHIVE_MARKER: &str = ".backy-archive";
+
+/// Prefixo dos diretórios de snapshots que estão sendo removidos
+pub const TRASH_PREFIX: &str = ".trash-";
+
+/// Um snapshot (diretório datado) encontrado no arquivo de backups
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    /// Nome do diretório do snapshot
+    pub name: String,
+    /// Data em que o snapshot foi criado
+    pub date: NaiveDate,
+    /// Caminho completo para o diretório do snapshot
+    pub path: PathBuf,
+}
+
+/// Caminho do arquivo de log do backup `name` dentro do snapshot `snapshot_dir`
+pub fn log_path(snapshot_dir: &Path, name: &str) -> PathBuf {
+    snapshot_dir
+        .join(METADATA_DIR)
+        .join("logs")
+        .join(format!("{}.log", name))
+}
+
+/// Caminho do arquivo que marca um snapshot como parcial, ou seja, um snapshot
+/// em que algum backup falhou ou foi interrompido
+pub fn partial_marker(snapshot_dir: &Path) -> PathBuf {
+    snapshot_dir.join(METADATA_DIR).join("partial")
+}
+
+/// Marca o snapshot como parcial, registrando os backups que falharam e o
+/// motivo. Se não houver falhas, remove uma marcação anterior.
+pub fn mark_partial(snapshot_dir: &Path, failures: &[(String, String)]) -> io::Result<()> {
+    let marker = partial_marker(snapshot_dir);
+    if failures.is_empty() {
+        return match fs::remove_file(&marker) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        };
+    }
+    fs::create_dir_all(snapshot_dir.join(METADATA_DIR))?;
+    let content: String = failures
+        .iter()
+        .map(|(name, reason)| {
+            let reason = reason.lines().next().unwrap_or_default();
+            format!("{}: {}\n", name, reason)
+        })
+        .collect();
+    fs::write(marker, content)
+}
+
+/// Espaço do sistema de arquivos onde fica um caminho
+#[derive(Debug, Clone, Copy)]
+pub struct DiskSpace {
+    /// Tamanho total do sistema de arquivos, em bytes
+    pub total: u64,
+    /// Espaço disponível para usuários comuns, em bytes
+    pub available: u64,
+}
+
+/// Consulta o espaço do sistema de arquivos onde fica `path`
+pub fn disk_space(path: &Path) -> io::Result<DiskSpace> {
+    let c_path = CString::new(path.as_os_str().as_bytes())?;
+    let mut stat = MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: `c_path` é uma string C válida e `stat` só é lido após o
+    // `statvfs` preenchê-lo com sucesso
+    let stat = unsafe {
+        if libc::statvfs(c_path.as_ptr(), stat.as_mut_ptr()) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        stat.assume_init()
+    };
+    let fragment = stat.f_frsize as u64;
+    Ok(DiskSpace {
+        total: stat.f_blocks as u64 * fragment,
+        available: stat.f_bavail as u64 * fragment,
+    })
+}
+
+/// Espaço em disco usado exclusivamente pelos diretórios `paths`, ou seja, o
+/// espaço liberado ao removê-los. Como os snapshots compartilham arquivos por
+/// hardlinks, um arquivo só conta quando todos os seus links estão dentro dos
+/// diretórios.
+pub fn exclusive_size(paths: &[PathBuf]) -> io::Result<u64> {
+    let mut size = ExclusiveSize::default();
+    for path in paths {
+        size.add(path)?;
+    }
+    Ok(size.total())
+}
+
+/// Cálculo do [`exclusive_size`] que pode ser feito aos poucos: cada diretório
+/// é percorrido uma única vez, e o total é atualizado a cada um que é somado
+#[derive(Default)]
+pub struct ExclusiveSize {
+    /// (dispositivo, inode) -> (links encontrados, links totais)
+    inodes: HashMap<(u64, u64), (u64, u64)>,
+    total: u64,
+}
+
+impl ExclusiveSize {
+    /// Soma o diretório `path` ao conjunto
+    pub fn add(&mut self, path: &Path) -> io::Result<()> {
+        let mut pending = vec![path.to_path_buf()];
+        while let Some(path) = pending.pop() {
+            let meta = fs::symlink_metadata(&path)?;
+            let allocated = meta.blocks() * 512;
+            if meta.is_dir() {
+                self.total += allocated;
+                for entry in fs::read_dir(&path)? {
+                    pending.push(entry?.path());
+                }
+                continue;
+            }
+            let (found, links) = self
+                .inodes
+                .entry((meta.dev(), meta.ino()))
+                .or_insert((0, meta.nlink()));
+            *found += 1;
+            if found == links {
+                self.total += allocated;
+            }
+        }
+        Ok(())
+    }
+
+    /// Espaço liberado ao remover todos os diretórios somados
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+}
+
+/// Soma o tamanho dos arquivos dentro de `path`, contando uma única vez os
+/// arquivos com vários hardlinks
+pub fn content_size(path: &Path) -> io::Result<u64> {
+    let mut inodes = HashSet::new();
+    let mut size = 0;
+    let mut pending = vec![path.to_path_buf()];
+    while let Some(path) = pending.pop() {
+        let meta = fs::symlink_metadata(&path)?;
+        if meta.is_dir() {
+            for entry in fs::read_dir(&path)? {
+                pending.push(entry?.path());
+            }
+        } else if inodes.insert((meta.dev(), meta.ino())) {
+            size += meta.len();
+        }
+    }
+    Ok(size)
+}
+
+/// Backups que falharam no snapshot, segundo a marcação de snapshot parcial
+pub fn partial_backups(snapshot_dir: &Path) -> Vec<String> {
+    fs::read_to_string(partial_marker(snapshot_dir))
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| Some(line.split_once(": ")?.0.to_string()))
+        .collect()
+}
+
+/// Procura, do mais recente para o mais antigo, até `limit` snapshots com uma
+/// cópia bem-sucedida do backup `name`, ignorando o snapshot `current` (que
+/// está sendo criado). Uma cópia é considerada bem-sucedida se o backup não
+/// falhou no snapshot e tem um manifesto (em snapshots criados antes dos
+/// manifestos, basta que o diretório exista).
+pub fn previous_copies(
+    archive_path: &Path,
+    current: &Path,
+    name: &str,
+    limit: usize,
+) -> Vec<Snapshot> {
+    let snapshots = list_snapshots(archive_path).unwrap_or_default();
+    snapshots
+        .into_iter()
+        .rev()
+        .filter(|snapshot| {
+            if snapshot.path == current
+                || !snapshot.path.join(name).is_dir()
+                || partial_backups(&snapshot.path).iter().any(|n| n == name)
+            {
+                return false;
+            }
+            let recorded = Manifest::recorded_backups(&snapshot.path);
+            recorded.is_empty() || recorded.iter().any(|n| n == name)
+        })
+        .take(limit)
+        .collect()
+}
+
+/// Lista os snapshots presentes no arquivo de backups, do mais antigo para o
+/// mais recente. Entradas que não são snapshots são ignoradas.
+pub fn list_snapshots(archive_path: &Path) -> BackyResult<Vec<Snapshot>> {
+    let entries = match fs::read_dir(archive_path) {
+        Ok(entries) => entries,
+        Err(err) => {
+            return Err(Box::new(ErrArchiveRead {
+                path: archive_path.to_owned(),
+                err,
+            }))
+        }
+    };
+    let mut snapshots: Vec<Snapshot> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().is_dir())
+        .filter_map(|entry| {
+            let name = entry.file_name().to_str()?.to_string();
+            let date = NaiveDate::parse_from_str(&name, SNAPSHOT_FORMAT).ok()?;
+            Some(Snapshot {
+                name,
+                date,
+                path: entry.path(),
+            })
+        })
+        .collect();
+    snapshots.sort_by_key(|snapshot| snapshot.date);
+    Ok(snapshots)
+}
+
+/// Procura um snapshot pelo nome do diretório
+pub fn find_snapshot(archive_path: &Path, name: &str) -> BackyResult<Snapshot> {
+    let name = name.trim_end_matches('/');
+    let snapshots = list_snapshots(archive_path)?;
+    // NOTE: `latest` é aceito como apelido para o alvo do link `latest`, que
+    // só aponta para snapshots completos. O diretório mais recente só é usado
+    // quando o link não existe.
+    let found = if name == LATEST_LINK {
+        match latest_name(archive_path) {
+            Some(target) => snapshots
+                .into_iter()
+                .find(|snapshot| snapshot.name == target),
+            None => snapshots.into_iter().last(),
+        }
+    } else {
+        snapshots.into_iter().find(|snapshot| snapshot.name == name)
+    };
+    match found {
+        Some(snapshot) => Ok(snapshot),
+        None => Err(Box::new(ErrNoSuchSnapshot {
+            name: name.to_string(),
+        })),
+    }
+}
+
+/// Remove um snapshot de forma que ele nunca pareça válido pela metade: o
+/// diretório é primeiro renomeado para a lixeira (`.trash-<nome>`) e só então
+/// apagado. Uma remoção interrompida pode ser retomada por `empty_trash`.
+pub fn delete_snapshot(snapshot: &Snapshot) -> io::Result<()> {
+    // NOTE: uma remoção interrompida do mesmo snapshot pode ter deixado a
+    // lixeira para trás, então procura um nome ainda livre
+    let trash = (0..)
+        .map(|attempt| match attempt {
+            0 => format!("{}{}", TRASH_PREFIX, snapshot.name),
+            n => format!("{}{}.{}", TRASH_PREFIX, snapshot.name, n),
+        })
+        .map(|name| snapshot.path.with_file_name(name))
+        .find(|trash| fs::symlink_metadata(trash).is_err())
+        .unwrap_or_default();
+    fs::rename(&snapshot.path, &trash)?;
+    remove_tree(&trash)
+}
+
+/// Termina de apagar os snapshots deixados na lixeira por remoções
+/// interrompidas. Devolve os diretórios que não puderam ser apagados.
+pub fn empty_trash(archive_path: &Path) -> io::Result<Vec<(PathBuf, io::Error)>> {
+    let mut failures = Vec::new();
+    for entry in fs::read_dir(archive_path)? {
+        let path = entry?.path();
+        let is_trash = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with(TRASH_PREFIX));
+        if !is_trash {
+            continue;
+        }
+        if let Err(err) = remove_tree(&path) {
+            failures.push((path, err));
+        }
+    }
+    Ok(failures)
+}
+
+/// Aponta o link `latest_link` para `snapshot_dir`. O link novo é criado ao
+/// lado e renomeado por cima do antigo, para que `latest` nunca fique faltando
+/// ou pela metade.
+pub fn point_latest(latest_link: &Path, snapshot_dir: &Path) -> io::Result<()> {
+    let tmp_link = latest_link.with_file_name(format!(".{}.tmp", LATEST_LINK));
+    fs::remove_file(&tmp_link).ok();
+    let result = symlink(snapshot_dir, &tmp_link).and_then(|_| fs::rename(&tmp_link, latest_link));
+    if result.is_err() {
+        fs::remove_file(&tmp_link).ok();
+    }
+    result
+}
+
+/// Nome do snapshot para onde o link `latest` aponta, se ele existir
+pub fn latest_name(archive_path: &Path) -> Option<String> {
+    let target = fs::read_link(archive_path.join(LATEST_LINK)).ok()?;
+    Some(target.file_name()?.to_str()?.to_string())
+}
+
+/// Confere se `archive_path` está no disco configurado pelo usuário. Evita que
+/// os backups sejam escritos no disco raíz quando o disco externo não está
+/// conectado.
+pub fn check_destination(config: &Config) -> BackyResult<()> {
+    let device = match &config.archive_device {
+        Some(device) => device,
+        None => return Ok(()),
+    };
+    let fail = |reason: String| -> BackyResult<()> {
+        Err(Box::new(ErrWrongArchiveDevice {
+            path: config.archive_path.clone(),
+            reason,
+        }))
+    };
+
+    let archive_meta = match fs::metadata(&config.archive_path) {
+        Ok(meta) if meta.is_dir() => meta,
+        _ => return fail("the directory doesn't exist (is the disk plugged in?)".into()),
+    };
+    for (kind, id) in identifiers(device) {
+        let dev_path = Path::new("/dev/disk").join(format!("by-{}", kind)).join(id);
+        match fs::metadata(&dev_path) {
+            Ok(dev_meta) if dev_meta.rdev() == archive_meta.dev() => {}
+            Ok(_) => {
+                return fail(format!(
+                    "it is not on the filesystem with {} '{}'",
+                    kind, id
+                ))
+            }
+            Err(_) => return fail(format!("no filesystem with {} '{}' is connected", kind, id)),
+        }
+    }
+    if let Some(expected) = &device.marker_id {
+        let marker = config.archive_path.join(ARCHIVE_MARKER);
+        match fs::read_to_string(&marker) {
+            Ok(found) if found.trim() == expected => {}
+            Ok(found) => {
+                return fail(format!(
+                    "'{}' identifies archive '{}', expected '{}'",
+                    marker.display(),
+                    found.trim(),
+                    expected
+                ))
+            }
+            Err(_) => {
+                return fail(format!(
+                    "marker file '{}' not found. Create it with the archive ID to use this disk",
+                    marker.display()
+                ))
+            }
+        }
+    }
+    Ok(())
+}
+
+// #######################
+//   Definições privadas
+// #######################
+/// Apaga um diretório inteiro. Se faltar permissão, libera a escrita nos
+/// subdiretórios (que podem ter sido copiados como somente leitura) e tenta
+/// novamente.
+fn remove_tree(path: &Path) -> io::Result<()> {
+    match fs::remove_dir_all(path) {
+        Err(err) if err.kind() == io::ErrorKind::PermissionDenied => {
+            make_dirs_writable(path)?;
+            fs::remove_dir_all(path)
+        }
+        result => result,
+    }
+}
+
+/// Dá ao dono permissão de escrita e acesso em todos os subdiretórios de `path`
+fn make_dirs_writable(path: &Path) -> io::Result<()> {
+    let meta = fs::symlink_metadata(path)?;
+    if !meta.is_dir() {
+        return Ok(());
+    }
+    let mut permissions = meta.permissions();
+    permissions.set_mode(permissions.mode() | 0o700);
+    fs::set_permissions(path, permissions)?;
+    for entry in fs::read_dir(path)? {
+        make_dirs_writable(&entry?.path())?;
+    }
+    Ok(())
+}
+
+/// Identificações de dispositivo configuradas, no formato usado pelos
+/// diretórios de `/dev/disk`
+fn identifiers(device: &ArchiveDevice) -> Vec<(&'static str, &str)> {
+    let mut ids = Vec::new();
+    if let Some(uuid) = &device.uuid {
+        ids.push(("uuid", uuid.as_str()));
+    }
+    if let Some(label) = &device.label {
+        ids.push(("label", label.as_str()));
+    }
+    ids
+}
+
+// #######################
+//         Erros
+// #######################
+/// Erro lançado quando o arquivo de backups não está no disco esperado
+struct ErrWrongArchiveDevice {
+    path: PathBuf,
+    reason: String,
+}
+impl BackyError for ErrWrongArchiveDevice {
+    fn get_err_msg(&self) -> String {
+        format!(
+            "refusing to use backup archive '{}': {}",
+            self.path.display(),
+            self.reason
+        )
+    }
+}
+
+/// Erro lançado quando não é possível ler o diretório do arquivo de backups
+struct ErrArchiveRead {
+    path: PathBuf,
+    err: io::Error,
+}
+impl BackyError for ErrArchiveRead {
+    fn get_err_msg(&self) -> String {
+        format!(
+            "unable to read backup archive '{}':\n{}",
+            self.path.display(),
+            self.err
+        )
+    }
+}
+
+/// Erro lançado quando o snapshot pedido pelo usuário não existe
+struct ErrNoSuchSnapshot {
+    name: String,
+}
+impl BackyError for ErrNoSuchSnapshot {
+    fn get_err_msg(&self) -> String {
+        format!("snapshot '{}' doesn't exist in the archive", self.name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn latest_resolves_to_link_target() {
+        let archive = tempfile::tempdir().unwrap();
+        for name in ["20261016", "20261017", "20261018"] {
+            fs::create_dir(archive.path().join(name)).unwrap();
+        }
+        let find = |name: &str| {
+            find_snapshot(archive.path(), name)
+                .unwrap_or_else(|err| panic!("{}", err.get_err_msg()))
+                .name
+        };
+        // Sem o link, o diretório mais recente é usado
+        assert_eq!(find(LATEST_LINK), "20261018");
+        // Com o link, um snapshot mais novo (e parcial) é ignorado
+        point_latest(
+            &archive.path().join(LATEST_LINK),
+            &archive.path().join("20261017"),
+        )
+        .unwrap();
+        assert_eq!(find(LATEST_LINK), "20261017");
+        assert_eq!(find("20261018/"), "20261018");
+    }
+
+    #[test]
+    fn delete_snapshot_skips_stale_trash() {
+        let archive = tempfile::tempdir().unwrap();
+        let stale = archive.path().join(format!("{}20261017", TRASH_PREFIX));
+        fs::create_dir_all(stale.join("old")).unwrap();
+        fs::create_dir_all(archive.path().join("20261017/docs")).unwrap();
+        let snapshot = find_snapshot(archive.path(), "20261017")
+            .unwrap_or_else(|err| panic!("{}", err.get_err_msg()));
+
+        delete_snapshot(&snapshot).unwrap();
+        assert!(!snapshot.path.exists());
+        // A lixeira antiga continua lá, e é apagada por `empty_trash`
+        assert!(stale.join("old").exists());
+        assert!(empty_trash(archive.path()).unwrap().is_empty());
+        assert_eq!(fs::read_dir(archive.path()).unwrap().count(), 0);
+    }
+}
@@ -0,0 +1,152 @@
+use crate::error::BackyError;
+
+use std::{
+    fmt,
+    process::{self, Child},
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+// #######################
+//   Definições públicas
+// #######################
+/// Motivo pelo qual uma tarefa foi interrompida antes de terminar
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CancelReason {
+    /// O tempo limite do backup ou da execução inteira expirou
+    TimedOut,
+    /// O usuário pediu a interrupção do programa (Ctrl-C ou SIGTERM)
+    Interrupted,
+}
+
+impl CancelReason {
+    /// Cria o erro que representa o cancelamento do backup `name`
+    pub fn into_error(self, name: &str) -> Box<dyn BackyError> {
+        Box::new(ErrCancelled {
+            name: name.to_string(),
+            reason: self,
+        })
+    }
+}
+
+impl fmt::Display for CancelReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CancelReason::TimedOut => write!(f, "timed out"),
+            CancelReason::Interrupted => write!(f, "interrupted"),
+        }
+    }
+}
+
+/// Indica quando uma tarefa deve ser cancelada: ao atingir o prazo ou quando o
+/// programa receber um sinal de interrupção
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CancelToken {
+    deadline: Option<Instant>,
+}
+
+impl CancelToken {
+    /// Cria um token que expira no menor dos prazos informados
+    pub fn new(deadlines: &[Option<Instant>]) -> Self {
+        Self {
+            deadline: deadlines.iter().flatten().min().copied(),
+        }
+    }
+
+    /// Devolve o motivo do cancelamento, caso a tarefa deva ser cancelada
+    pub fn check(&self) -> Option<CancelReason> {
+        if interrupted() {
+            Some(CancelReason::Interrupted)
+        } else if self
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            Some(CancelReason::TimedOut)
+        } else {
+            None
+        }
+    }
+}
+
+/// Instala o tratamento de Ctrl-C e SIGTERM, que apenas marca o programa como
+/// interrompido para que as tarefas em andamento terminem de forma limpa. Um
+/// segundo sinal encerra o programa imediatamente.
+pub fn install_handler() {
+    let result = ctrlc::set_handler(|| {
+        if INTERRUPTED.swap(true, Ordering::SeqCst) {
+            process::exit(130);
+        }
+    });
+    // NOTE: o handler só pode ser instalado uma vez, e uma segunda instalação
+    // não muda o comportamento
+    result.ok();
+}
+
+/// Checa se o programa recebeu um sinal de interrupção
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Espera um processo filho terminar, matando todo o seu grupo de processos
+/// caso o token seja cancelado. O filho precisa ter sido criado em um grupo
+/// próprio (`CommandExt::process_group(0)`).
+pub fn wait_child(
+    child: &mut Child,
+    token: &CancelToken,
+) -> std::io::Result<Result<process::ExitStatus, CancelReason>> {
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Ok(status));
+        }
+        if let Some(reason) = token.check() {
+            kill_group(child)?;
+            return Ok(Err(reason));
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+// #######################
+//   Definições privadas
+// #######################
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Intervalo entre verificações do estado de um processo filho
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Tempo que um processo tem para terminar após o SIGTERM antes de receber um
+/// SIGKILL
+const KILL_GRACE: Duration = Duration::from_secs(5);
+
+/// Termina o grupo de processos do filho, primeiro com SIGTERM e, se ele não
+/// terminar a tempo, com SIGKILL
+fn kill_group(child: &mut Child) -> std::io::Result<()> {
+    let pgid = -(child.id() as libc::pid_t);
+    // SAFETY: `kill` apenas envia um sinal para o grupo do processo filho
+    unsafe { libc::kill(pgid, libc::SIGTERM) };
+    let started = Instant::now();
+    while started.elapsed() < KILL_GRACE {
+        if child.try_wait()?.is_some() {
+            return Ok(());
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+    // SAFETY: idem
+    unsafe { libc::kill(pgid, libc::SIGKILL) };
+    child.wait().map(|_| ())
+}
+
+// #######################
+//         Erros
+// #######################
+/// Erro lançado quando um backup é cancelado antes de terminar
+struct ErrCancelled {
+    name: String,
+    reason: CancelReason,
+}
+impl BackyError for ErrCancelled {
+    fn get_err_msg(&self) -> String {
+        format!("'{}' backup {} before finishing", self.name, self.reason)
+    }
+}
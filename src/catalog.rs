@@ -0,0 +1,152 @@
+use crate::{
+    error::BackyResult,
+    rclone::{self, RemoteEntry},
+    volumes,
+};
+
+use chrono::NaiveDate;
+
+// #######################
+//   Definições públicas
+// #######################
+/// Prefixo dos arquivos enviados aos remotes
+pub const ARCHIVE_PREFIX: &str = "backy_";
+
+/// Um arquivo enviado pelo backy a um remote, possivelmente dividido em volumes
+pub struct RemoteArchive {
+    /// Nome do arquivo (ou o nome base dos volumes), como no registro de envios
+    pub name: String,
+    /// Data do snapshot enviado
+    pub date: NaiveDate,
+    /// Arquivos que o compõem no remote: o próprio arquivo, ou os volumes e o
+    /// índice
+    pub files: Vec<String>,
+    /// Tamanho total em bytes, se o remote informar o de todos os arquivos
+    pub size: Option<u64>,
+}
+
+/// Conteúdo de um remote
+pub struct Catalog {
+    /// Arquivos do backy, do mais antigo para o mais recente
+    pub archives: Vec<RemoteArchive>,
+    /// Arquivos que o backy não reconhece
+    pub unknown: Vec<RemoteEntry>,
+}
+
+/// Lista os arquivos de `target`, agrupando os volumes de cada arquivo do backy
+pub fn scan(target: &str) -> BackyResult<Catalog> {
+    let mut archives: Vec<RemoteArchive> = Vec::new();
+    let mut unknown = Vec::new();
+    for entry in rclone::list(target)? {
+        let Some((name, date)) = parse_name(&entry.name) else {
+            unknown.push(entry);
+            continue;
+        };
+        let size = u64::try_from(entry.size).ok();
+        match archives.iter_mut().find(|archive| archive.name == name) {
+            Some(archive) => {
+                archive.files.push(entry.name);
+                archive.size = archive.size.zip(size).map(|(a, b)| a + b);
+            }
+            None => archives.push(RemoteArchive {
+                name,
+                date,
+                files: vec![entry.name],
+                size,
+            }),
+        }
+    }
+    archives.sort_by(|a, b| a.date.cmp(&b.date).then_with(|| a.name.cmp(&b.name)));
+    for archive in &mut archives {
+        archive.files.sort();
+    }
+    Ok(Catalog { archives, unknown })
+}
+
+/// Interpreta o nome de um arquivo enviado pelo backy
+/// (`backy_<data>.tar[.<compressão>][.age][.<volume>|.index]`), devolvendo o
+/// nome do arquivo a que ele pertence e a data do snapshot. Datas sem os zeros
+/// à esquerda (`backy_2024-1-5.tar.gz`) também são aceitas.
+pub fn parse_name(file_name: &str) -> Option<(String, NaiveDate)> {
+    let rest = file_name.strip_prefix(ARCHIVE_PREFIX)?;
+    let (raw_date, extension) = rest.split_once('.')?;
+    let date = NaiveDate::parse_from_str(raw_date, "%Y-%m-%d").ok()?;
+
+    let mut parts: Vec<&str> = extension.split('.').collect();
+    let is_part = |part: &str| {
+        part == volumes::INDEX_EXTENSION
+            || (part.len() >= 3 && part.chars().all(|c| c.is_ascii_digit()))
+    };
+    if parts.len() > 1 && parts.last().is_some_and(|part| is_part(part)) {
+        parts.pop();
+    }
+    let known = match parts.as_slice() {
+        ["tar", rest @ ..] => match rest {
+            [] | ["age"] => true,
+            [compression] | [compression, "age"] => ["gz", "zst", "xz"].contains(compression),
+            _ => false,
+        },
+        _ => false,
+    };
+    match known {
+        true => Some((
+            format!("{}{}.{}", ARCHIVE_PREFIX, raw_date, parts.join(".")),
+            date,
+        )),
+        false => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parsed(file_name: &str) -> Option<(String, String)> {
+        parse_name(file_name).map(|(name, date)| (name, date.to_string()))
+    }
+
+    #[test]
+    fn parses_single_file_archives() {
+        for name in [
+            "backy_2024-01-05.tar",
+            "backy_2024-01-05.tar.gz",
+            "backy_2024-01-05.tar.zst.age",
+            "backy_2024-01-05.tar.age",
+        ] {
+            assert_eq!(parsed(name), Some((name.into(), "2024-01-05".into())));
+        }
+    }
+
+    #[test]
+    fn groups_volumes_and_index_under_the_archive_name() {
+        let archive = Some(("backy_2024-01-05.tar.xz.age".into(), "2024-01-05".into()));
+        assert_eq!(parsed("backy_2024-01-05.tar.xz.age.001"), archive);
+        assert_eq!(parsed("backy_2024-01-05.tar.xz.age.1234"), archive);
+        assert_eq!(parsed("backy_2024-01-05.tar.xz.age.index"), archive);
+    }
+
+    #[test]
+    fn accepts_dates_without_padding() {
+        assert_eq!(
+            parsed("backy_2024-1-5.tar.gz"),
+            Some(("backy_2024-1-5.tar.gz".into(), "2024-01-05".into()))
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_names() {
+        for name in [
+            "notes.txt",
+            "backy_2024-01-05",
+            "backy_2024-13-05.tar.gz",
+            "backy_latest.tar.gz",
+            "backy_2024-01-05.zip",
+            "backy_2024-01-05.tar.rar",
+            "backy_2024-01-05.tar.gz.01",
+            "backy_2024-01-05.tar.gz.bak",
+            "other_2024-01-05.tar.gz",
+        ] {
+            assert_eq!(parsed(name), None, "{}", name);
+        }
+    }
+}
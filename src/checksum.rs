@@ -0,0 +1,47 @@
+use sha2::{Digest, Sha256};
+use std::io::{self, Write};
+
+// #######################
+//   Definições públicas
+// #######################
+/// Repassa tudo o que é escrito para `inner`, calculando o SHA-256 do conteúdo
+pub struct Sha256Writer<W: Write> {
+    inner: W,
+    hasher: Sha256,
+}
+
+impl<W: Write> Sha256Writer<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+        }
+    }
+
+    /// Devolve a saída e o SHA-256 do que foi escrito, em hexadecimal
+    pub fn finish(self) -> (W, String) {
+        (self.inner, hex(self.hasher))
+    }
+}
+
+impl<W: Write> Write for Sha256Writer<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Termina o cálculo do SHA-256, devolvendo-o em hexadecimal
+pub fn hex(hasher: Sha256) -> String {
+    format!("{:x}", hasher.finalize())
+}
+
+/// Checa se uma string é um SHA-256 em hexadecimal
+pub fn is_sha256(raw: &str) -> bool {
+    raw.len() == 64 && raw.chars().all(|c| c.is_ascii_hexdigit())
+}
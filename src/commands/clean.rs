@@ -1,83 +1,364 @@
-use std::{
-    fs::{self, DirEntry},
-    io,
-    path::PathBuf,
-    thread,
-};
+use std::{io, path::PathBuf, thread};
 
-use chrono::{NaiveDate, Utc};
+use chrono::Utc;
 
 use crate::{
+    archive::{self, Snapshot},
+    config::{ByteSize, CapacityPolicy, Config},
     error::{BackyError, BackyResult},
-    logging::{info, log},
+    logging::{info, log, warning},
+    pins::Pins,
+    retention,
 };
 
-use super::BackyCommand;
+use super::{BackyCommand, ErrUnexpectedArg};
 
 /// Remove backups antigos.
-pub struct CmdClean;
-impl BackyCommand for CmdClean {
-    fn execute(&self, config: crate::config::Config) -> BackyResult<()> {
-        info!(
-            "Removing backups versions older than {} days.",
-            config.remove_older_than
-        );
-        let backup_dir = PathBuf::from(&config.archive_path);
-        let backup_list: Vec<DirEntry> = match fs::read_dir(backup_dir) {
-            Ok(list) => list,
-            Err(err) => return Err(Box::new(ErrNoArchiveDir { err })),
+pub struct CmdClean {
+    /// Apenas mostra o que seria removido, sem remover nada
+    dry_run: bool,
+}
+
+impl CmdClean {
+    pub fn from_args(args: &[String]) -> BackyResult<Self> {
+        let mut dry_run = false;
+        for arg in args {
+            match arg.as_str() {
+                "--dry-run" | "-n" => dry_run = true,
+                _ => return Err(Box::new(ErrUnexpectedArg { arg: arg.clone() })),
+            }
         }
-        .map(|backup| backup.unwrap())
-        .filter(|backup| {
-            // Ignora arquivos que não são backups
-            NaiveDate::parse_from_str(backup.file_name().to_str().unwrap(), "%Y%m%d").is_ok()
-        })
-        .collect();
+        Ok(Self { dry_run })
+    }
+}
 
-        let num_backups = backup_list.len();
-        let backups_to_remove: Vec<DirEntry> = backup_list
+impl BackyCommand for CmdClean {
+    fn execute(&self, config: Config) -> BackyResult<()> {
+        archive::check_destination(&config)?;
+        if !self.dry_run {
+            empty_trash(&config);
+        }
+        let snapshots = archive::list_snapshots(&config.archive_path)?;
+        let pins = Pins::load(&config.archive_path)?;
+        let latest = archive::latest_name(&config.archive_path);
+        let mut decisions: Vec<Decision> = keep_reasons(&config, &snapshots)?
             .into_iter()
-            .filter(|backup| {
-                let today = Utc::today().naive_utc();
-                // NOTE: seguro, já que testamos que podemos fazer a conversão no
-                // filter de backup_list
-                let backup_date =
-                    NaiveDate::parse_from_str(backup.file_name().to_str().unwrap(), "%Y%m%d")
-                        .unwrap();
-                (today - backup_date).num_days() >= config.remove_older_than
+            .zip(&snapshots)
+            .map(|(reasons, snapshot)| {
+                if pins.is_pinned(&snapshot.name) {
+                    Decision::Protected("pinned")
+                } else if latest.as_deref() == Some(snapshot.name.as_str()) {
+                    Decision::Protected("latest")
+                } else if reasons.is_empty() {
+                    Decision::Remove("no rule keeps it")
+                } else {
+                    Decision::Keep(reasons)
+                }
             })
             .collect();
-
-        // Impede (por segurança) que o programa remova todos os backups
-        if num_backups - backups_to_remove.len() >= 1 {
-            let mut handles = vec![];
-            for backup in backups_to_remove {
-                handles.push(thread::spawn(move || {
-                    info!("Removing backup '{}'", backup.file_name().to_str().unwrap());
-                    fs::remove_dir_all(backup.path()).unwrap();
-                }));
+        if let Some(capacity) = &config.capacity {
+            for idx in plan_capacity(&config, capacity, &snapshots, &decisions)? {
+                decisions[idx] = Decision::Remove("to meet the space goals");
             }
-            for handle in handles {
-                handle.join().unwrap();
+        }
+
+        // Mostra a decisão tomada para cada snapshot
+        let mut backups_to_remove = Vec::new();
+        for (snapshot, decision) in snapshots.iter().zip(decisions) {
+            match decision {
+                Decision::Keep(reasons) => {
+                    info!("Keeping '{}' ({}).", snapshot.name, reasons.join(", "));
+                }
+                Decision::Protected(reason) => {
+                    info!("Keeping '{}' ({}).", snapshot.name, reason);
+                }
+                Decision::Remove(reason) => backups_to_remove.push((snapshot.clone(), reason)),
             }
+        }
+
+        if self.dry_run {
+            return preview_removal(&snapshots, &backups_to_remove);
+        }
+
+        // Impede (por segurança) que o programa remova todos os backups
+        if snapshots.len() - backups_to_remove.len() >= 1 {
+            remove_snapshots(backups_to_remove)
         } else {
             info!("Only one backup remaining. No cleanup needed.");
+            Ok(())
+        }
+    }
+}
+
+/// Remove os snapshots mais antigos caso o espaço livre no disco do arquivo
+/// esteja abaixo da meta configurada. Usado antes do `update`, quando
+/// `prune_before_update` está ativado.
+pub fn prune_for_space(config: &Config) -> BackyResult<()> {
+    let capacity = match &config.capacity {
+        Some(capacity) if capacity.prune_before_update => capacity,
+        _ => return Ok(()),
+    };
+    let min_free = match capacity.min_free_space {
+        Some(min_free) => min_free,
+        None => return Ok(()),
+    };
+    let space = disk_space(config)?;
+    if ByteSize(space.available) >= min_free.resolve(space.total) {
+        return Ok(());
+    }
+
+    info!(
+        "Only {} free on the archive disk (goal: {}). Removing old snapshots before the update.",
+        ByteSize(space.available),
+        min_free
+    );
+    empty_trash(config);
+    let snapshots = archive::list_snapshots(&config.archive_path)?;
+    let pins = Pins::load(&config.archive_path)?;
+    let latest = archive::latest_name(&config.archive_path);
+    let decisions: Vec<_> = snapshots
+        .iter()
+        .map(|snapshot| {
+            if pins.is_pinned(&snapshot.name) {
+                Decision::Protected("pinned")
+            } else if latest.as_deref() == Some(snapshot.name.as_str()) {
+                Decision::Protected("latest")
+            } else {
+                Decision::Keep(vec![])
+            }
+        })
+        .collect();
+    let to_remove = plan_capacity(config, capacity, &snapshots, &decisions)?
+        .into_iter()
+        .map(|idx| (snapshots[idx].clone(), "to meet the space goals"))
+        .collect();
+    remove_snapshots(to_remove)
+}
+
+// #######################
+//   Definições privadas
+// #######################
+/// Decisão tomada para um snapshot: mantê-lo (com as regras que o mantêm),
+/// mantê-lo por estar protegido (fixado ou apontado por `latest`) ou removê-lo
+/// (com o motivo)
+enum Decision {
+    Keep(Vec<&'static str>),
+    Protected(&'static str),
+    Remove(&'static str),
+}
+
+/// Remove os snapshots em paralelo, coletando as falhas
+fn remove_snapshots(snapshots: Vec<(Snapshot, &'static str)>) -> BackyResult<()> {
+    let mut handles = vec![];
+    for (backup, reason) in snapshots {
+        let name = backup.name.clone();
+        handles.push((
+            name,
+            thread::spawn(move || {
+                info!("Removing backup '{}' ({}).", backup.name, reason);
+                archive::delete_snapshot(&backup).map_err(|err| err.to_string())
+            }),
+        ));
+    }
+    let failures: Vec<(String, String)> = handles
+        .into_iter()
+        .filter_map(|(name, handle)| match handle.join() {
+            Ok(Ok(())) => None,
+            Ok(Err(reason)) => Some((name, reason)),
+            Err(_) => Some((name, "removal thread panicked".into())),
+        })
+        .collect();
+    match failures.is_empty() {
+        true => Ok(()),
+        false => Err(Box::new(ErrRemoveFailed { failures })),
+    }
+}
+
+/// Termina de apagar snapshots cuja remoção foi interrompida em uma execução
+/// anterior. Uma falha aqui não impede a limpeza nem a atualização: a lixeira
+/// fica para a próxima execução.
+fn empty_trash(config: &Config) {
+    let failures = match archive::empty_trash(&config.archive_path) {
+        Ok(failures) => failures,
+        Err(err) => vec![(config.archive_path.clone(), err)],
+    };
+    for (path, err) in failures {
+        warning!(
+            "Unable to empty trash '{}': {}. It will be retried on the next run.",
+            path.display(),
+            err
+        );
+    }
+}
+
+/// Mostra os snapshots que seriam removidos e estima o espaço liberado
+fn preview_removal(
+    snapshots: &[Snapshot],
+    to_remove: &[(Snapshot, &'static str)],
+) -> BackyResult<()> {
+    if snapshots.len() == to_remove.len() {
+        info!("Dry run: no rule keeps any snapshot, so nothing would be removed (at least one backup is always kept).");
+        return Ok(());
+    }
+    for (snapshot, reason) in to_remove {
+        info!("Dry run: would remove '{}' ({}).", snapshot.name, reason);
+    }
+    let paths: Vec<PathBuf> = to_remove.iter().map(|(s, _)| s.path.clone()).collect();
+    let bytes = exclusive_size(&paths)?;
+    info!(
+        "Dry run: {} snapshot(s) would be removed, freeing about {}.",
+        to_remove.len(),
+        ByteSize(bytes)
+    );
+    Ok(())
+}
+
+/// Escolhe, do mais antigo para o mais recente, os snapshots mantidos pelas
+/// regras de retenção que também precisam ser removidos para atingir as metas
+/// de espaço. Devolve os índices desses snapshots.
+fn plan_capacity(
+    config: &Config,
+    policy: &CapacityPolicy,
+    snapshots: &[Snapshot],
+    decisions: &[Decision],
+) -> BackyResult<Vec<usize>> {
+    let space = disk_space(config)?;
+    let min_free = policy
+        .min_free_space
+        .map(|amount| amount.resolve(space.total));
+    let archive_size = match policy.max_archive_size {
+        Some(_) => {
+            let all: Vec<PathBuf> = snapshots.iter().map(|s| s.path.clone()).collect();
+            exclusive_size(&all)?
+        }
+        None => 0,
+    };
+    let goal_met = |freed: u64| {
+        min_free.is_none_or(|min| ByteSize(space.available + freed) >= min)
+            && policy
+                .max_archive_size
+                .is_none_or(|max| ByteSize(archive_size.saturating_sub(freed)) <= max)
+    };
+
+    // NOTE: snapshots protegidos contam como mantidos, mas nunca são candidatos
+    // à remoção
+    let (mut removed, mut kept): (Vec<PathBuf>, Vec<usize>) = (Vec::new(), Vec::new());
+    let mut protected = 0;
+    for (idx, decision) in decisions.iter().enumerate() {
+        match decision {
+            Decision::Remove(_) => removed.push(snapshots[idx].path.clone()),
+            Decision::Keep(_) => kept.push(idx),
+            Decision::Protected(_) => protected += 1,
+        }
+    }
+    // NOTE: os hardlinks já vistos são guardados entre as iterações, de forma
+    // que cada snapshot é percorrido uma única vez
+    let mut freed = archive::ExclusiveSize::default();
+    for path in &removed {
+        freed.add(path).map_err(space_estimate_failed)?;
+    }
+
+    let min_keep = policy.min_keep.max(1);
+    let mut extra = Vec::new();
+    for idx in kept.iter().copied() {
+        if goal_met(freed.total()) || protected + kept.len() - extra.len() <= min_keep {
+            break;
         }
+        extra.push(idx);
+        freed
+            .add(&snapshots[idx].path)
+            .map_err(space_estimate_failed)?;
+    }
+    if !goal_met(freed.total()) {
+        warning!(
+            "The space goals can't be met while keeping at least {} snapshot(s).",
+            min_keep
+        );
+    }
+    Ok(extra)
+}
+
+/// Consulta o espaço do disco do arquivo de backups
+fn disk_space(config: &Config) -> BackyResult<archive::DiskSpace> {
+    archive::disk_space(&config.archive_path).map_err(|err| {
+        Box::new(ErrSpaceEstimate {
+            reason: err.to_string(),
+        }) as Box<dyn BackyError>
+    })
+}
+
+/// Estima o espaço liberado ao remover os snapshots em `paths`
+fn exclusive_size(paths: &[PathBuf]) -> BackyResult<u64> {
+    archive::exclusive_size(paths).map_err(space_estimate_failed)
+}
 
-        Ok(())
+fn space_estimate_failed(err: io::Error) -> Box<dyn BackyError> {
+    Box::new(ErrSpaceEstimate {
+        reason: err.to_string(),
+    })
+}
+
+/// Aplica a política de retenção do usuário, devolvendo para cada snapshot a
+/// lista de regras que o mantêm
+fn keep_reasons(config: &Config, snapshots: &[Snapshot]) -> BackyResult<Vec<Vec<&'static str>>> {
+    let dates: Vec<_> = snapshots.iter().map(|snapshot| snapshot.date).collect();
+    match (&config.retention, config.remove_older_than) {
+        (Some(policy), _) => {
+            info!("Applying retention rules.");
+            Ok(retention::keep_reasons(&dates, policy))
+        }
+        (None, Some(max_days)) => {
+            info!("Removing backups versions older than {} days.", max_days);
+            let today = Utc::today().naive_utc();
+            Ok(retention::keep_reasons_by_age(&dates, today, max_days))
+        }
+        // NOTE: apenas as metas de espaço decidem o que é removido
+        (None, None) if config.capacity.is_some() => {
+            Ok(vec![vec!["within space goals"]; dates.len()])
+        }
+        (None, None) => Err(Box::new(ErrNoRetention)),
     }
 }
 
 // #######################
 //         Erros
 // #######################
-/// Erro lançado quando não é possível criar o diretório para arquivar os
-/// backups
-struct ErrNoArchiveDir {
-    err: io::Error,
+/// Erro lançado quando não é possível medir o espaço usado pelos snapshots ou
+/// o espaço livre no disco
+struct ErrSpaceEstimate {
+    reason: String,
 }
-impl BackyError for ErrNoArchiveDir {
+impl BackyError for ErrSpaceEstimate {
+    fn get_err_msg(&self) -> String {
+        format!("unable to measure disk space:\n{}", self.reason)
+    }
+}
+
+/// Erro lançado quando alguns snapshots não puderam ser removidos. Os que
+/// chegaram a ir para a lixeira são apagados na próxima execução.
+struct ErrRemoveFailed {
+    /// Snapshots (ou diretórios) e os motivos das falhas
+    failures: Vec<(String, String)>,
+}
+impl BackyError for ErrRemoveFailed {
+    fn get_err_msg(&self) -> String {
+        let failures: Vec<String> = self
+            .failures
+            .iter()
+            .map(|(name, reason)| format!("  {}: {}", name, reason))
+            .collect();
+        format!(
+            "unable to remove {} snapshot(s):\n{}",
+            self.failures.len(),
+            failures.join("\n")
+        )
+    }
+}
+
+/// Erro lançado quando a configuração não define nenhuma política de retenção
+struct ErrNoRetention;
+impl BackyError for ErrNoRetention {
     fn get_err_msg(&self) -> String {
-        format!("unable to create backup dir:\n{}", self.err)
+        "no retention policy configured. Set `remove_older_than`, a `[retention]` or a `[capacity]` section".into()
     }
 }
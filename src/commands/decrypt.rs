@@ -0,0 +1,205 @@
+use super::{BackyCommand, ErrMissingValue, ErrUnexpectedArg, ErrUnknownRemote};
+
+use crate::{
+    config::{self, Config, EncryptionSettings},
+    crypt,
+    error::{BackyError, BackyResult},
+    logging::{info, log},
+};
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+// #######################
+//   Definições públicas
+// #######################
+/// Abre um arquivo criptografado enviado ao remote, recuperando o arquivo
+/// comprimido original para a restauração.
+pub struct CmdDecrypt {
+    input: PathBuf,
+    /// Onde gravar o arquivo aberto. Por padrão, o próprio arquivo sem a
+    /// extensão `.age`.
+    output: Option<PathBuf>,
+    /// Arquivo com as chaves privadas, no lugar do configurado
+    identity: Option<PathBuf>,
+    /// Arquivo com a senha, no lugar da configurada
+    passphrase_file: Option<PathBuf>,
+    /// Remote cuja criptografia deve ser usada, no lugar da seção
+    /// `[encryption]`
+    remote: Option<String>,
+}
+
+impl CmdDecrypt {
+    pub fn from_args(args: &[String]) -> BackyResult<Self> {
+        let mut input = None;
+        let mut output = None;
+        let mut identity = None;
+        let mut passphrase_file = None;
+        let mut remote = None;
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--output" | "-o" => match args.next() {
+                    Some(path) => output = Some(PathBuf::from(path)),
+                    None => return Err(Box::new(ErrMissingValue { flag: "--output" })),
+                },
+                "--identity" | "-i" => match args.next() {
+                    Some(path) => identity = Some(PathBuf::from(path)),
+                    None => return Err(Box::new(ErrMissingValue { flag: "--identity" })),
+                },
+                "--passphrase-file" => match args.next() {
+                    Some(path) => passphrase_file = Some(PathBuf::from(path)),
+                    None => {
+                        return Err(Box::new(ErrMissingValue {
+                            flag: "--passphrase-file",
+                        }))
+                    }
+                },
+                "--remote" => match args.next() {
+                    Some(name) => remote = Some(name.clone()),
+                    None => return Err(Box::new(ErrMissingValue { flag: "--remote" })),
+                },
+                _ if input.is_none() && !arg.starts_with('-') => input = Some(PathBuf::from(arg)),
+                _ => return Err(Box::new(ErrUnexpectedArg { arg: arg.clone() })),
+            }
+        }
+        match input {
+            Some(input) => Ok(Self {
+                input,
+                output,
+                identity,
+                passphrase_file,
+                remote,
+            }),
+            None => Err(Box::new(ErrNoInput)),
+        }
+    }
+}
+
+impl BackyCommand for CmdDecrypt {
+    // NOTE: com a chave na linha de comando, o arquivo pode ser aberto em
+    // outra máquina, onde o backy não está configurado
+    fn run(&self) -> BackyResult<()> {
+        let has_key = self.identity.is_some() || self.passphrase_file.is_some();
+        match self.remote.is_none() && has_key {
+            true => self.decrypt(None),
+            false => self.execute(config::load()?),
+        }
+    }
+
+    fn execute(&self, config: Config) -> BackyResult<()> {
+        let encryption = match &self.remote {
+            Some(name) => match config.remotes().remove(name) {
+                Some(remote) => remote.encryption,
+                None => {
+                    return Err(Box::new(ErrUnknownRemote {
+                        remote: name.clone(),
+                    }))
+                }
+            },
+            None => config.encryption.clone(),
+        };
+        self.decrypt(encryption)
+    }
+}
+
+impl CmdDecrypt {
+    /// Abre o arquivo com a criptografia configurada, se houver, e as chaves
+    /// passadas na linha de comando
+    fn decrypt(&self, encryption: Option<EncryptionSettings>) -> BackyResult<()> {
+        let encryption = match &self.passphrase_file {
+            Some(path) => Some(EncryptionSettings {
+                passphrase_file: Some(path.clone()),
+                passphrase_command: None,
+                ..encryption.unwrap_or_default()
+            }),
+            None => encryption,
+        };
+        let output = match &self.output {
+            Some(output) => output.clone(),
+            None => default_output(&self.input)?,
+        };
+        let fail = |path: &Path, err: io::Error| -> Box<dyn BackyError> {
+            Box::new(ErrDecryptFile {
+                path: path.to_owned(),
+                err,
+            })
+        };
+        let input = File::open(&self.input).map_err(|err| fail(&self.input, err))?;
+        let mut out = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&output)
+            .map(BufWriter::new)
+            .map_err(|err| fail(&output, err))?;
+
+        info!(
+            "Decrypting '{}' into '{}'.",
+            self.input.display(),
+            output.display()
+        );
+        let result = crypt::decrypt(
+            encryption.as_ref(),
+            self.identity.as_deref(),
+            BufReader::new(input),
+            &mut out,
+        )
+        .and_then(|_| out.flush().map_err(|err| fail(&output, err)));
+        if result.is_err() {
+            // NOTE: um arquivo aberto pela metade não serve para a restauração
+            drop(out);
+            fs::remove_file(&output).ok();
+        }
+        result
+    }
+}
+
+// #######################
+//   Definições privadas
+// #######################
+/// Caminho do arquivo aberto quando o usuário não escolhe um: o próprio
+/// arquivo sem a extensão `.age`
+fn default_output(input: &Path) -> BackyResult<PathBuf> {
+    let is_encrypted = input.extension().is_some_and(|ext| ext == crypt::EXTENSION);
+    match is_encrypted {
+        true => Ok(input.with_extension("")),
+        false => Err(Box::new(ErrNoOutput)),
+    }
+}
+
+// #######################
+//         Erros
+// #######################
+/// Erro lançado quando o usuário não informa o arquivo a ser aberto
+struct ErrNoInput;
+impl BackyError for ErrNoInput {
+    fn get_err_msg(&self) -> String {
+        "no file given. Try `backy help` for aditional information.".into()
+    }
+}
+
+/// Erro lançado quando não é possível escolher o nome do arquivo aberto
+struct ErrNoOutput;
+impl BackyError for ErrNoOutput {
+    fn get_err_msg(&self) -> String {
+        format!(
+            "the file doesn't end with '.{}'. Choose where to write it with --output",
+            crypt::EXTENSION
+        )
+    }
+}
+
+/// Erro lançado quando não é possível ler o arquivo criptografado ou gravar o
+/// arquivo aberto
+struct ErrDecryptFile {
+    path: PathBuf,
+    err: io::Error,
+}
+impl BackyError for ErrDecryptFile {
+    fn get_err_msg(&self) -> String {
+        format!("unable to access '{}':\n{}", self.path.display(), self.err)
+    }
+}
@@ -10,8 +10,30 @@ USAGE:
 where COMMAND is one of:
     help      Write this help message.
     update    Update backup files to most recent version.
-    remote    Update remote drive with most recent backup version.
-    clean     Remove old backups.";
+    remote    Send snapshots to the configured remotes (by default, the `latest`
+              one to every remote that is due).
+              Usage: backy remote [REMOTE...] [SNAPSHOT... | --last N | --since DATE | --all]
+              Use `backy remote prune [REMOTE...] [--dry-run]` to remove old
+              archives following each remote's `retention` rules,
+              `backy remote list [REMOTE...]` to see the archives on each remote
+              and `backy remote status [REMOTE...]` to compare them with the
+              local snapshots.
+    decrypt   Decrypt an archive downloaded from a remote.
+              Usage: backy decrypt FILE [--output PATH] [--identity FILE]
+                                   [--passphrase-file FILE] [--remote REMOTE]
+              The archives are standard age files, so `age -d` also opens them.
+    clean     Remove old backups.
+              Usage: backy clean [--dry-run]
+    list      List the snapshots in the archive.
+    pin       Protect a snapshot from being removed by clean.
+              Usage: backy pin SNAPSHOT [--note TEXT]
+    unpin     Remove the protection added by pin.
+              Usage: backy unpin SNAPSHOT
+    repair    Find and fix incomplete snapshots, a broken `latest` link and
+              leftovers from interrupted runs.
+              Usage: backy repair [--yes]
+    verify    Check snapshot integrity against the recorded manifests.
+              Usage: backy verify [SNAPSHOT]";
 
 // #######################
 //   Definições públicas
@@ -0,0 +1,206 @@
+use super::{
+    remote::has_backups, user_has_rclone, BackyCommand, ErrNoRclone, ErrNoRemotes,
+    ErrRemotesFailed, ErrUnexpectedArg, ErrUnknownRemote,
+};
+
+use crate::{
+    archive,
+    catalog::{self, Catalog, RemoteArchive},
+    config::{ByteSize, Config, RemoteSettings},
+    error::{BackyError, BackyResult},
+    ledger::{Ledger, Upload},
+    logging::{info, log},
+};
+
+use chrono::Utc;
+
+// #######################
+//   Definições públicas
+// #######################
+/// Lista os arquivos enviados a cada remote, com a data, o tamanho, o SHA-256 e
+/// se o envio foi conferido
+pub struct CmdRemoteList {
+    /// Remotes escolhidos pelo nome. Por padrão, todos.
+    names: Vec<String>,
+}
+
+/// Compara os snapshots locais com os arquivos de cada remote: snapshots que
+/// nunca foram enviados, a idade da cópia mais recente e arquivos que o backy
+/// não reconhece
+pub struct CmdRemoteStatus {
+    /// Remotes escolhidos pelo nome. Por padrão, todos.
+    names: Vec<String>,
+}
+
+impl CmdRemoteList {
+    pub fn from_args(args: &[String]) -> BackyResult<Self> {
+        Ok(Self {
+            names: parse_names(args)?,
+        })
+    }
+}
+
+impl CmdRemoteStatus {
+    pub fn from_args(args: &[String]) -> BackyResult<Self> {
+        Ok(Self {
+            names: parse_names(args)?,
+        })
+    }
+}
+
+impl BackyCommand for CmdRemoteList {
+    fn execute(&self, config: Config) -> BackyResult<()> {
+        let ledger = Ledger::load(&config.archive_path)?;
+        for_each_remote(&config, &self.names, "list", |name, settings, catalog| {
+            println!("Remote '{}' (`{}`):", name, settings.target);
+            if catalog.archives.is_empty() {
+                println!("  no archives");
+                return Ok(());
+            }
+            println!(
+                "  {:<10}  {:>10}  {:<8}  {:<40}  SHA-256",
+                "DATE", "SIZE", "VERIFIED", "ARCHIVE"
+            );
+            for archive in &catalog.archives {
+                let upload = find_upload(&ledger, name, &archive.name);
+                let size = archive
+                    .size
+                    .map_or("?".to_string(), |size| ByteSize(size).to_string());
+                let verified = match upload {
+                    Some(upload) if upload.verified => "yes",
+                    Some(_) => "no",
+                    None => "-",
+                };
+                let sha256 = upload
+                    .and_then(|upload| upload.sha256.as_deref())
+                    .unwrap_or("-");
+                println!(
+                    "  {:<10}  {:>10}  {:<8}  {:<40}  {}",
+                    archive.date.format("%Y-%m-%d"),
+                    size,
+                    verified,
+                    archive.name,
+                    sha256
+                );
+            }
+            Ok(())
+        })
+    }
+}
+
+impl BackyCommand for CmdRemoteStatus {
+    fn execute(&self, config: Config) -> BackyResult<()> {
+        let snapshots = archive::list_snapshots(&config.archive_path)?;
+        let ledger = Ledger::load(&config.archive_path)?;
+        let today = Utc::today().naive_utc();
+        for_each_remote(&config, &self.names, "check", |name, settings, catalog| {
+            println!("Remote '{}' (`{}`):", name, settings.target);
+            // NOTE: um arquivo no remote só conta se o envio terminou e foi
+            // registrado. Um envio interrompido pode ter deixado apenas alguns
+            // volumes.
+            let uploaded =
+                |archive: &&RemoteArchive| find_upload(&ledger, name, &archive.name).is_some();
+            match catalog.archives.iter().rev().find(uploaded) {
+                Some(newest) => {
+                    let age = (today - newest.date).num_days();
+                    println!("  Newest archive: {} ({} day(s) old)", newest.name, age);
+                }
+                None => println!("  Newest archive: none"),
+            }
+
+            let missing: Vec<_> = snapshots
+                .iter()
+                .filter(|snapshot| has_backups(snapshot, settings.backups.as_deref()))
+                .filter(|snapshot| {
+                    !catalog
+                        .archives
+                        .iter()
+                        .filter(uploaded)
+                        .any(|archive| archive.date == snapshot.date)
+                })
+                .collect();
+            match missing.is_empty() {
+                true => println!("  Every local snapshot is on the remote."),
+                false => {
+                    println!("  Never uploaded ({}):", missing.len());
+                    for snapshot in missing {
+                        println!("    {}", snapshot.name);
+                    }
+                }
+            }
+
+            if !catalog.unknown.is_empty() {
+                println!("  Not created by backy ({}):", catalog.unknown.len());
+                for entry in &catalog.unknown {
+                    println!("    {}", entry.name);
+                }
+            }
+            Ok(())
+        })
+    }
+}
+
+// #######################
+//   Definições privadas
+// #######################
+/// Lê os nomes dos remotes passados para `remote list` e `remote status`
+fn parse_names(args: &[String]) -> BackyResult<Vec<String>> {
+    args.iter()
+        .map(|arg| match arg.starts_with('-') {
+            true => Err(Box::new(ErrUnexpectedArg { arg: arg.clone() }) as Box<dyn BackyError>),
+            false => Ok(arg.clone()),
+        })
+        .collect()
+}
+
+/// Lista o conteúdo de cada remote escolhido (ou de todos) e o entrega a `f`.
+/// Um remote que não pode ser listado não impede que os demais sejam, e as
+/// falhas são reportadas juntas com a ação do comando (`action`).
+fn for_each_remote(
+    config: &Config,
+    names: &[String],
+    action: &'static str,
+    mut f: impl FnMut(&str, &RemoteSettings, &Catalog) -> BackyResult<()>,
+) -> BackyResult<()> {
+    archive::check_destination(config)?;
+    if !user_has_rclone() {
+        return Err(Box::new(ErrNoRclone));
+    }
+    let configured = config.remotes();
+    if configured.is_empty() {
+        return Err(Box::new(ErrNoRemotes));
+    }
+    if let Some(name) = names.iter().find(|name| !configured.contains_key(*name)) {
+        return Err(Box::new(ErrUnknownRemote {
+            remote: name.clone(),
+        }));
+    }
+
+    let mut failures = Vec::new();
+    for (name, settings) in &configured {
+        if !names.is_empty() && !names.contains(name) {
+            continue;
+        }
+        info!(
+            "Listing archives on remote '{}' (`{}`).",
+            name, settings.target
+        );
+        let result =
+            catalog::scan(&settings.target).and_then(|catalog| f(name, settings, &catalog));
+        if let Err(err) = result {
+            failures.push((name.clone(), err.get_err_msg()));
+        }
+    }
+    match failures.is_empty() {
+        true => Ok(()),
+        false => Err(Box::new(ErrRemotesFailed { action, failures })),
+    }
+}
+
+/// Registro do envio de um arquivo ao remote
+fn find_upload<'a>(ledger: &'a Ledger, remote: &str, file: &str) -> Option<&'a Upload> {
+    ledger
+        .uploads(remote)
+        .iter()
+        .find(|upload| upload.file == file)
+}
@@ -0,0 +1,69 @@
+use super::{BackyCommand, ErrUnexpectedArg};
+
+use crate::{
+    archive,
+    config::Config,
+    error::BackyResult,
+    logging::{info, log},
+    manifest::Manifest,
+    pins::Pins,
+};
+
+// #######################
+//   Definições públicas
+// #######################
+/// Lista os snapshots do arquivo de backups, com os backups que cada um contém
+/// e os que estão fixados.
+pub struct CmdList;
+
+impl CmdList {
+    pub fn from_args(args: &[String]) -> BackyResult<Self> {
+        match args.first() {
+            None => Ok(Self),
+            Some(arg) => Err(Box::new(ErrUnexpectedArg { arg: arg.clone() })),
+        }
+    }
+}
+
+impl BackyCommand for CmdList {
+    fn execute(&self, config: Config) -> BackyResult<()> {
+        archive::check_destination(&config)?;
+        let snapshots = archive::list_snapshots(&config.archive_path)?;
+        if snapshots.is_empty() {
+            info!("The archive has no snapshots.");
+            return Ok(());
+        }
+        let pins = Pins::load(&config.archive_path)?;
+        let latest = archive::latest_name(&config.archive_path);
+
+        println!("{:<10}  {:<30}  NOTES", "SNAPSHOT", "BACKUPS");
+        for snapshot in &snapshots {
+            let backups = Manifest::recorded_backups(&snapshot.path);
+            let backups = match backups.is_empty() {
+                true => "-".to_string(),
+                false => backups.join(", "),
+            };
+            let mut notes = Vec::new();
+            if latest.as_deref() == Some(snapshot.name.as_str()) {
+                notes.push("latest".to_string());
+            }
+            if archive::partial_marker(&snapshot.path).exists() {
+                notes.push("partial".to_string());
+            }
+            if let Some(pin) = pins.pins.get(&snapshot.name) {
+                match &pin.note {
+                    Some(note) => notes.push(format!("pinned: {}", note)),
+                    None => notes.push("pinned".to_string()),
+                }
+            }
+            let line = format!(
+                "{:<10}  {:<30}  {}",
+                snapshot.name,
+                backups,
+                notes.join(", ")
+            );
+            println!("{}", line.trim_end());
+        }
+        Ok(())
+    }
+}
@@ -1,13 +1,20 @@
 mod clean;
+mod decrypt;
 mod help;
+mod inventory;
+mod list;
+mod pin;
+mod prune;
 mod remote;
+mod repair;
 mod update;
+mod verify;
 
 use crate::{
-    config::Config,
+    config::{self, Config},
     error::{BackyError, BackyResult},
+    runner::Process,
 };
-use std::process::{self, Stdio};
 
 // #######################
 //   Definições públicas
@@ -16,6 +23,12 @@ use std::process::{self, Stdio};
 pub trait BackyCommand {
     /// Executa um comando
     fn execute(&self, config: Config) -> BackyResult<()>;
+
+    /// Carrega a configuração do usuário e executa o comando. Comandos que nem
+    /// sempre precisam da configuração podem evitar carregá-la.
+    fn run(&self) -> BackyResult<()> {
+        self.execute(config::load()?)
+    }
 }
 
 /// Cria e devolve o comando correspondente à lista argumentos
@@ -25,9 +38,20 @@ pub fn from_args(args: &[String]) -> BackyResult<Box<dyn BackyCommand>> {
     }
     match args[1].as_str() {
         "help" => Ok(Box::new(help::CmdHelp)),
-        "clean" => Ok(Box::new(clean::CmdClean)),
+        "clean" => Ok(Box::new(clean::CmdClean::from_args(&args[2..])?)),
         "update" => Ok(Box::new(update::CmdUpdate)),
-        "remote" => Ok(Box::new(remote::CmdRemote)),
+        "remote" => match args.get(2).map(String::as_str) {
+            Some("prune") => Ok(Box::new(prune::CmdRemotePrune::from_args(&args[3..])?)),
+            Some("list") => Ok(Box::new(inventory::CmdRemoteList::from_args(&args[3..])?)),
+            Some("status") => Ok(Box::new(inventory::CmdRemoteStatus::from_args(&args[3..])?)),
+            _ => Ok(Box::new(remote::CmdRemote::from_args(&args[2..])?)),
+        },
+        "list" => Ok(Box::new(list::CmdList::from_args(&args[2..])?)),
+        "pin" => Ok(Box::new(pin::CmdPin::from_args(&args[2..])?)),
+        "unpin" => Ok(Box::new(pin::CmdUnpin::from_args(&args[2..])?)),
+        "repair" => Ok(Box::new(repair::CmdRepair::from_args(&args[2..])?)),
+        "decrypt" => Ok(Box::new(decrypt::CmdDecrypt::from_args(&args[2..])?)),
+        "verify" => Ok(Box::new(verify::CmdVerify::from_args(&args[2..])?)),
         cmd => Err(Box::new(ErrBadCommand {
             cmd: cmd.to_string(),
         })),
@@ -39,20 +63,12 @@ pub fn from_args(args: &[String]) -> BackyResult<Box<dyn BackyCommand>> {
 // #######################
 /// Checa se o usuário tem o programa `rsync` instalado
 fn user_has_rsync() -> bool {
-    process::Command::new("rsync")
-        .arg("--version")
-        .stdout(Stdio::null())
-        .status()
-        .is_ok()
+    Process::new("rsync").arg("--version").run().is_ok()
 }
 
 /// Checa se o usuário tem o programa `rclone` instalado
 fn user_has_rclone() -> bool {
-    process::Command::new("rclone")
-        .arg("--version")
-        .stdout(Stdio::null())
-        .status()
-        .is_ok()
+    Process::new("rclone").arg("--version").run().is_ok()
 }
 
 // #######################
@@ -77,6 +93,29 @@ impl BackyError for ErrBadCommand {
     }
 }
 
+/// Erro lançado quando o usuário passa um argumento que o comando não aceita
+struct ErrUnexpectedArg {
+    arg: String,
+}
+impl BackyError for ErrUnexpectedArg {
+    fn get_err_msg(&self) -> String {
+        format!(
+            "unexpected argument '{}'. Try `backy help` for aditional information.",
+            self.arg
+        )
+    }
+}
+
+/// Erro lançado quando uma opção é passada sem o seu valor
+struct ErrMissingValue {
+    flag: &'static str,
+}
+impl BackyError for ErrMissingValue {
+    fn get_err_msg(&self) -> String {
+        format!("option '{}' requires a value", self.flag)
+    }
+}
+
 /// Erro lançado quando não é possível encontrar o executável do rsync no PATH
 /// do usuário
 struct ErrNoRsync;
@@ -94,3 +133,46 @@ impl BackyError for ErrNoRclone {
         "unable to find `rclone` executable".into()
     }
 }
+
+/// Erro lançado quando nenhum remote está configurado
+struct ErrNoRemotes;
+impl BackyError for ErrNoRemotes {
+    fn get_err_msg(&self) -> String {
+        "no remote configured. Set rclone_remote or add a [remotes.<name>] section".into()
+    }
+}
+
+/// Erro lançado quando o remote escolhido não está configurado
+struct ErrUnknownRemote {
+    remote: String,
+}
+impl BackyError for ErrUnknownRemote {
+    fn get_err_msg(&self) -> String {
+        format!("remote '{}' is not configured", self.remote)
+    }
+}
+
+/// Erro lançado quando um comando falha em algum dos remotes
+struct ErrRemotesFailed {
+    /// O que o comando fazia nos remotes ("update", "prune", ...)
+    action: &'static str,
+    /// Remotes que falharam e o motivo
+    failures: Vec<(String, String)>,
+}
+impl BackyError for ErrRemotesFailed {
+    fn get_err_msg(&self) -> String {
+        let mut msg = format!(
+            "unable to {} {} remote(s):",
+            self.action,
+            self.failures.len()
+        );
+        for (remote, reason) in &self.failures {
+            msg.push_str(&format!(
+                "\n  {}: {}",
+                remote,
+                reason.replace('\n', "\n    ")
+            ));
+        }
+        msg
+    }
+}
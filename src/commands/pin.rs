@@ -0,0 +1,107 @@
+use super::{BackyCommand, ErrMissingValue, ErrUnexpectedArg};
+
+use crate::{
+    archive,
+    config::Config,
+    error::{BackyError, BackyResult},
+    logging::{info, log, warning},
+    pins::{Pin, Pins},
+};
+
+use chrono::Local;
+
+// #######################
+//   Definições públicas
+// #######################
+/// Fixa um snapshot, impedindo que ele seja removido pelo `clean`.
+pub struct CmdPin {
+    snapshot: String,
+    note: Option<String>,
+}
+
+impl CmdPin {
+    pub fn from_args(args: &[String]) -> BackyResult<Self> {
+        let mut snapshot = None;
+        let mut note = None;
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--note" => match args.next() {
+                    Some(text) => note = Some(text.clone()),
+                    None => return Err(Box::new(ErrMissingValue { flag: "--note" })),
+                },
+                _ if snapshot.is_none() && !arg.starts_with('-') => snapshot = Some(arg.clone()),
+                _ => return Err(Box::new(ErrUnexpectedArg { arg: arg.clone() })),
+            }
+        }
+        match snapshot {
+            Some(snapshot) => Ok(Self { snapshot, note }),
+            None => Err(Box::new(ErrNoSnapshot)),
+        }
+    }
+}
+
+impl BackyCommand for CmdPin {
+    fn execute(&self, config: Config) -> BackyResult<()> {
+        archive::check_destination(&config)?;
+        let snapshot = archive::find_snapshot(&config.archive_path, &self.snapshot)?;
+        let mut pins = Pins::load(&config.archive_path)?;
+        let pin = Pin {
+            pinned_at: Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+            note: self.note.clone(),
+        };
+        if pins.pins.insert(snapshot.name.clone(), pin).is_some() {
+            info!("Updating pin of snapshot '{}'.", snapshot.name);
+        } else {
+            info!("Pinning snapshot '{}'.", snapshot.name);
+        }
+        pins.save(&config.archive_path)
+    }
+}
+
+/// Remove a fixação de um snapshot, que volta a seguir a política de retenção.
+pub struct CmdUnpin {
+    snapshot: String,
+}
+
+impl CmdUnpin {
+    pub fn from_args(args: &[String]) -> BackyResult<Self> {
+        match args {
+            [] => Err(Box::new(ErrNoSnapshot)),
+            [snapshot] => Ok(Self {
+                snapshot: snapshot.clone(),
+            }),
+            [_, extra, ..] => Err(Box::new(ErrUnexpectedArg { arg: extra.clone() })),
+        }
+    }
+}
+
+impl BackyCommand for CmdUnpin {
+    fn execute(&self, config: Config) -> BackyResult<()> {
+        archive::check_destination(&config)?;
+        let mut pins = Pins::load(&config.archive_path)?;
+        // NOTE: o snapshot pode já ter sido removido manualmente, então o nome é
+        // procurado primeiro entre as fixações
+        let name = match pins.is_pinned(&self.snapshot) {
+            true => self.snapshot.clone(),
+            false => archive::find_snapshot(&config.archive_path, &self.snapshot)?.name,
+        };
+        if pins.pins.remove(&name).is_none() {
+            warning!("Snapshot '{}' is not pinned.", name);
+            return Ok(());
+        }
+        info!("Unpinning snapshot '{}'.", name);
+        pins.save(&config.archive_path)
+    }
+}
+
+// #######################
+//         Erros
+// #######################
+/// Erro lançado quando o usuário não informa o snapshot
+struct ErrNoSnapshot;
+impl BackyError for ErrNoSnapshot {
+    fn get_err_msg(&self) -> String {
+        "no snapshot given. Try `backy help` for aditional information.".into()
+    }
+}
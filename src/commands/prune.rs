@@ -0,0 +1,245 @@
+use super::{
+    user_has_rclone, BackyCommand, ErrNoRclone, ErrRemotesFailed, ErrUnexpectedArg,
+    ErrUnknownRemote,
+};
+
+use crate::{
+    archive,
+    catalog::{self, RemoteArchive},
+    config::{ByteSize, Config, RemoteRetention},
+    error::{BackyError, BackyResult},
+    ledger::Ledger,
+    logging::{info, log, warning},
+    rclone, retention,
+};
+
+use chrono::Utc;
+use std::path::Path;
+
+// #######################
+//   Definições públicas
+// #######################
+/// Remove do remote os arquivos antigos, seguindo as regras de retenção de
+/// cada remote. O último arquivo conferido após o envio nunca é removido.
+pub struct CmdRemotePrune {
+    /// Remotes escolhidos pelo nome. Por padrão, todos os que têm regras de
+    /// retenção.
+    names: Vec<String>,
+    /// Apenas mostra o que seria removido, sem remover nada
+    dry_run: bool,
+}
+
+impl CmdRemotePrune {
+    pub fn from_args(args: &[String]) -> BackyResult<Self> {
+        let mut names = Vec::new();
+        let mut dry_run = false;
+        for arg in args {
+            match arg.as_str() {
+                "--dry-run" | "-n" => dry_run = true,
+                _ if !arg.starts_with('-') => names.push(arg.clone()),
+                _ => return Err(Box::new(ErrUnexpectedArg { arg: arg.clone() })),
+            }
+        }
+        Ok(Self { names, dry_run })
+    }
+}
+
+impl BackyCommand for CmdRemotePrune {
+    fn execute(&self, config: Config) -> BackyResult<()> {
+        archive::check_destination(&config)?;
+        if !user_has_rclone() {
+            return Err(Box::new(ErrNoRclone));
+        }
+        let configured = config.remotes();
+        if let Some(name) = self.names.iter().find(|n| !configured.contains_key(*n)) {
+            return Err(Box::new(ErrUnknownRemote {
+                remote: name.clone(),
+            }));
+        }
+
+        let mut failures = Vec::new();
+        for (name, settings) in configured {
+            let chosen = self.names.contains(&name);
+            if !self.names.is_empty() && !chosen {
+                continue;
+            }
+            let Some(retention) = &settings.retention else {
+                if chosen {
+                    failures.push((name, ErrNoRemoteRetention.get_err_msg()));
+                }
+                continue;
+            };
+            let pruner = Pruner {
+                name: &name,
+                target: &settings.target,
+                archive_path: &config.archive_path,
+                dry_run: self.dry_run,
+            };
+            if let Err(err) = pruner.prune(retention) {
+                failures.push((name, err.get_err_msg()));
+            }
+        }
+        match failures.is_empty() {
+            true => Ok(()),
+            false => Err(Box::new(ErrRemotesFailed {
+                action: "prune",
+                failures,
+            })),
+        }
+    }
+}
+
+// #######################
+//   Definições privadas
+// #######################
+/// Decisão tomada para um arquivo do remote, como no `clean`
+enum Decision {
+    Keep(Vec<&'static str>),
+    Protected(&'static str),
+    Remove,
+}
+
+/// Limpeza de um dos remotes
+struct Pruner<'a> {
+    name: &'a str,
+    target: &'a str,
+    archive_path: &'a Path,
+    dry_run: bool,
+}
+
+impl Pruner<'_> {
+    fn prune(&self, retention: &RemoteRetention) -> BackyResult<()> {
+        info!(
+            "Listing archives on remote '{}' (`{}`).",
+            self.name, self.target
+        );
+        let catalog = catalog::scan(self.target)?;
+        if !catalog.unknown.is_empty() {
+            info!(
+                "Ignoring {} file(s) on remote '{}' that weren't created by backy.",
+                catalog.unknown.len(),
+                self.name
+            );
+        }
+        let archives = catalog.archives;
+        if archives.is_empty() {
+            info!("Remote '{}': no archives found.", self.name);
+            return Ok(());
+        }
+
+        // NOTE: sem um arquivo conferido, não há garantia de que algum arquivo
+        // do remote possa ser restaurado, então nada é removido
+        let mut ledger = Ledger::load(self.archive_path)?;
+        let last_verified = archives.iter().rposition(|archive| {
+            ledger
+                .uploads(self.name)
+                .iter()
+                .any(|upload| upload.file == archive.name && upload.verified)
+        });
+        let Some(last_verified) = last_verified else {
+            warning!(
+                "Remote '{}' has no verified archive. Nothing will be removed.",
+                self.name
+            );
+            return Ok(());
+        };
+
+        let decisions = keep_reasons(&archives, retention)
+            .into_iter()
+            .enumerate()
+            .map(|(idx, reasons)| {
+                if idx == last_verified {
+                    Decision::Protected("last verified")
+                } else if reasons.is_empty() {
+                    Decision::Remove
+                } else {
+                    Decision::Keep(reasons)
+                }
+            });
+        let mut to_remove = Vec::new();
+        for (archive, decision) in archives.iter().zip(decisions) {
+            match decision {
+                Decision::Keep(reasons) => {
+                    info!("Keeping '{}' ({}).", archive.name, reasons.join(", "));
+                }
+                Decision::Protected(reason) => {
+                    info!("Keeping '{}' ({}).", archive.name, reason);
+                }
+                Decision::Remove => to_remove.push(archive),
+            }
+        }
+
+        if self.dry_run {
+            for archive in &to_remove {
+                info!(
+                    "Dry run: would remove '{}' ({}).",
+                    archive.name,
+                    describe_size(archive)
+                );
+            }
+            info!(
+                "Dry run: {} archive(s) would be removed from remote '{}'.",
+                to_remove.len(),
+                self.name
+            );
+            return Ok(());
+        }
+        for archive in &to_remove {
+            info!(
+                "Removing '{}' from remote '{}' ({}).",
+                archive.name,
+                self.name,
+                describe_size(archive)
+            );
+            for file in &archive.files {
+                rclone::delete(&rclone::join(self.target, file))?;
+            }
+            ledger.forget(self.name, &archive.name);
+            ledger.save(self.archive_path)?;
+        }
+        info!(
+            "Remote '{}': removed {} archive(s).",
+            self.name,
+            to_remove.len()
+        );
+        Ok(())
+    }
+}
+
+/// Aplica as regras de retenção do remote, devolvendo para cada arquivo a lista
+/// de regras que o mantêm
+fn keep_reasons(archives: &[RemoteArchive], retention: &RemoteRetention) -> Vec<Vec<&'static str>> {
+    let dates: Vec<_> = archives.iter().map(|archive| archive.date).collect();
+    let mut reasons = retention::keep_reasons(&dates, &retention.rules);
+    if let Some(max_days) = retention.remove_older_than {
+        let today = Utc::today().naive_utc();
+        let by_age = retention::keep_reasons_by_age(&dates, today, max_days);
+        for (reasons, by_age) in reasons.iter_mut().zip(by_age) {
+            reasons.extend(by_age);
+        }
+    }
+    reasons
+}
+
+/// Tamanho e número de arquivos de um arquivo do remote, para as mensagens
+fn describe_size(archive: &RemoteArchive) -> String {
+    let size = archive
+        .size
+        .map_or("unknown size".into(), |size| ByteSize(size).to_string());
+    match archive.files.len() {
+        1 => size,
+        count => format!("{}, {} files", size, count),
+    }
+}
+
+// #######################
+//         Erros
+// #######################
+/// Erro lançado quando um remote escolhido pelo nome não tem regras de
+/// retenção
+struct ErrNoRemoteRetention;
+impl BackyError for ErrNoRemoteRetention {
+    fn get_err_msg(&self) -> String {
+        "no retention policy configured for this remote. Add a `retention` section to it".into()
+    }
+}
@@ -1,98 +1,873 @@
-use super::{user_has_rclone, BackyCommand, ErrNoRclone};
+use super::{
+    user_has_rclone, BackyCommand, ErrNoRclone, ErrNoRemotes, ErrRemotesFailed, ErrUnexpectedArg,
+};
 
 use crate::{
-    config::Config,
+    archive::{self, Snapshot},
+    checksum::{self, Sha256Writer},
+    config::{
+        ByteSize, CompressionFormat, CompressionSettings, Config, RemoteSettings, UploadMode,
+        UploadSettings,
+    },
+    crypt::{self, Key},
     error::{BackyError, BackyResult},
-    logging::{info, log},
+    ledger::{self, Ledger, Upload},
+    logging::{info, log, warning},
+    pack, rclone,
+    runner::{ErrProcess, Process},
+    volumes::{self, Volume, VolumeWriter},
 };
 
-use chrono::{Datelike, Utc};
+use chrono::{Local, NaiveDate};
+use sha2::{Digest, Sha256};
 use std::{
-    process::{self, Stdio},
+    env,
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
     str,
+    sync::{Arc, Mutex},
+    thread,
 };
-use tempfile::tempdir;
+use tempfile::{Builder, TempDir};
 
 // #######################
 //   Definições públicas
 // #######################
-pub struct CmdRemote;
+/// Envia snapshots comprimidos para os remotes configurados, em paralelo. Por
+/// padrão, envia apenas o snapshot apontado por `latest`.
+pub struct CmdRemote {
+    /// Remotes e snapshots escolhidos pelo nome. Os nomes que não são de um
+    /// remote configurado são tratados como nomes de snapshots.
+    names: Vec<String>,
+    rule: Option<Selection>,
+}
+
+impl CmdRemote {
+    pub fn from_args(args: &[String]) -> BackyResult<Self> {
+        let mut names = Vec::new();
+        let mut rule = None;
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let parsed = match arg.as_str() {
+                "--all" => Some(Selection::All),
+                "--last" => args
+                    .next()
+                    .and_then(|count| count.parse().ok())
+                    .filter(|&count| count > 0)
+                    .map(Selection::Last),
+                "--since" => args
+                    .next()
+                    .and_then(|date| parse_date(date))
+                    .map(Selection::Since),
+                _ if !arg.starts_with('-') => {
+                    names.push(arg.clone());
+                    continue;
+                }
+                _ => return Err(Box::new(ErrUnexpectedArg { arg: arg.clone() })),
+            };
+            match parsed {
+                Some(selection) if rule.is_none() => rule = Some(selection),
+                _ => return Err(Box::new(ErrBadSelection { arg: arg.clone() })),
+            }
+        }
+        Ok(Self { names, rule })
+    }
+}
+
 impl BackyCommand for CmdRemote {
-    /// Atualiza o drive externo com a versão atual do backup
+    /// Atualiza os remotes com os snapshots escolhidos
     fn execute(&self, config: Config) -> BackyResult<()> {
+        archive::check_destination(&config)?;
         if !user_has_rclone() {
             return Err(Box::new(ErrNoRclone));
         }
-        if !rclone_valid_remote(&config.rclone_remote) {
-            return Err(Box::new(ErrBadRemoteName));
+        let configured = config.remotes();
+        if configured.is_empty() {
+            return Err(Box::new(ErrNoRemotes));
         }
 
+        let (chosen, mut snapshot_names): (Vec<String>, Vec<String>) = self
+            .names
+            .iter()
+            .cloned()
+            .partition(|name| configured.contains_key(name));
+        let selection = match (&self.rule, snapshot_names.is_empty()) {
+            (Some(selection), true) => selection.clone(),
+            (None, false) => Selection::Named(snapshot_names),
+            (None, true) => Selection::Latest,
+            (Some(_), false) => {
+                return Err(Box::new(ErrBadSelection {
+                    arg: snapshot_names.remove(0),
+                }))
+            }
+        };
+        let snapshots = selection.resolve(&config.archive_path)?;
+
+        // NOTE: remotes escolhidos pelo nome ignoram a frequência e reenviam
+        // snapshots já enviados
+        let forced = !chosen.is_empty();
+        let ledger_lock = Arc::new(Mutex::new(()));
+        let mut handles = Vec::new();
+        for (name, settings) in configured {
+            if forced && !chosen.contains(&name) {
+                continue;
+            }
+            let run = RemoteRun {
+                name: name.clone(),
+                settings,
+                archive_path: config.archive_path.clone(),
+                allow_unencrypted: config.allow_unencrypted,
+                forced,
+                ledger_lock: Arc::clone(&ledger_lock),
+            };
+            let snapshots = snapshots.clone();
+            handles.push((name, thread::spawn(move || run.execute(&snapshots))));
+        }
+
+        // Um remote que falha não impede que os demais terminem
+        let mut failures = Vec::new();
+        for (name, handle) in handles {
+            let result = handle.join().unwrap_or_else(|_| {
+                Err(Box::new(ErrRemoteThread {
+                    remote: name.clone(),
+                }))
+            });
+            match result {
+                Ok(Outcome::Skipped) => {}
+                Ok(Outcome::Sent(0)) => {
+                    info!("Remote '{}': nothing new to send.", name);
+                }
+                Ok(Outcome::Sent(count)) => {
+                    info!("Remote '{}': sent {} snapshot(s).", name, count);
+                }
+                Err(err) => failures.push((name, err.get_err_msg())),
+            }
+        }
+        match failures.is_empty() {
+            true => Ok(()),
+            false => Err(Box::new(ErrRemotesFailed {
+                action: "update",
+                failures,
+            })),
+        }
+    }
+}
+
+// #######################
+//   Definições privadas
+// #######################
+/// Número de tentativas de envio de cada volume
+const VOLUME_ATTEMPTS: usize = 3;
+
+/// Extensão do arquivo com o estado de um envio em volumes que pode ser
+/// retomado
+const RESUME_EXTENSION: &str = "resume";
+
+/// Snapshots que devem ser enviados aos remotes
+#[derive(Clone)]
+enum Selection {
+    /// O snapshot apontado por `latest`
+    Latest,
+    /// Snapshots escolhidos pelo nome
+    Named(Vec<String>),
+    /// Os N snapshots mais recentes
+    Last(usize),
+    /// Os snapshots criados a partir de uma data
+    Since(NaiveDate),
+    /// Todos os snapshots
+    All,
+}
+
+impl Selection {
+    /// Encontra os snapshots escolhidos, do mais antigo para o mais recente
+    fn resolve(&self, archive_path: &Path) -> BackyResult<Vec<Snapshot>> {
+        let mut snapshots = match self {
+            Selection::Latest => {
+                let name = archive::latest_name(archive_path);
+                let name = name.as_deref().unwrap_or(archive::LATEST_LINK);
+                vec![archive::find_snapshot(archive_path, name)?]
+            }
+            Selection::Named(names) => names
+                .iter()
+                .map(|name| archive::find_snapshot(archive_path, name))
+                .collect::<BackyResult<_>>()?,
+            Selection::Last(count) => {
+                let snapshots = archive::list_snapshots(archive_path)?;
+                let skip = snapshots.len().saturating_sub(*count);
+                snapshots.into_iter().skip(skip).collect()
+            }
+            Selection::Since(date) => archive::list_snapshots(archive_path)?
+                .into_iter()
+                .filter(|snapshot| snapshot.date >= *date)
+                .collect(),
+            Selection::All => archive::list_snapshots(archive_path)?,
+        };
+        snapshots.sort_by_key(|snapshot| snapshot.date);
+        snapshots.dedup_by(|a, b| a.name == b.name);
+        if snapshots.is_empty() {
+            return Err(Box::new(ErrNothingSelected));
+        }
+        Ok(snapshots)
+    }
+}
+
+/// Resultado do envio a um remote
+enum Outcome {
+    /// O remote foi ignorado por causa da frequência configurada
+    Skipped,
+    /// Número de snapshots enviados
+    Sent(usize),
+}
+
+/// Envio dos snapshots a um dos remotes, feito em uma thread própria
+struct RemoteRun {
+    name: String,
+    settings: RemoteSettings,
+    archive_path: PathBuf,
+    allow_unencrypted: bool,
+    /// O remote foi escolhido pelo nome
+    forced: bool,
+    /// Protege o registro de envios, que é compartilhado pelos remotes
+    ledger_lock: Arc<Mutex<()>>,
+}
+
+impl RemoteRun {
+    fn execute(&self, snapshots: &[Snapshot]) -> BackyResult<Outcome> {
+        let name = &self.name;
+        let target = &self.settings.target;
+        if !rclone_valid_remote(target)? {
+            return Err(Box::new(ErrBadRemoteName {
+                target: target.clone(),
+            }));
+        }
+        let ledger = Ledger::load(&self.archive_path)?;
+        if !self.forced && self.is_recent(&ledger) {
+            return Ok(Outcome::Skipped);
+        }
+        let key = match &self.settings.encryption {
+            Some(settings) => Some(Key::load(settings)?),
+            None if self.allow_unencrypted => {
+                warning!(
+                    "Encryption is disabled: snapshots will be sent unencrypted to remote '{}'.",
+                    name
+                );
+                None
+            }
+            None => return Err(Box::new(ErrUnencrypted)),
+        };
+
         // Testa conexão com o remote do usuário
+        info!("Testing conection with remote '{}' (`{}`).", name, target);
+        let reached = Process::new("rclone")
+            .args(["lsf", "--max-depth", "1", target])
+            .transient()
+            .permanent_codes(&rclone::PERMANENT_EXIT_CODES)
+            .run();
+        match reached {
+            Ok(_) => {}
+            // NOTE: o diretório de um remote novo só é criado pelo primeiro envio
+            Err(err) if err.code() == Some(rclone::DIR_NOT_FOUND) => {}
+            Err(err) => return Err(Box::new(ErrInacessibleRemote { err })),
+        }
+
+        let compression = self.settings.compression.clone().unwrap_or_default();
+        let upload = self.settings.upload.clone().unwrap_or_default();
+        let volume_size = upload.volume_size.map(|size| size.0.max(1));
+        let staging = match (upload.mode, volume_size) {
+            (UploadMode::Staged, None) => Some(staging_dir(&upload)?),
+            _ => None,
+        };
+        let mut sent = 0;
+        for snapshot in snapshots {
+            let file_name = archive_file_name(snapshot, compression.format, key.is_some());
+            let uploaded = ledger.uploads(name).iter().any(|u| u.file == file_name);
+            if uploaded && !self.forced {
+                info!(
+                    "Snapshot '{}' was already sent to remote '{}'.",
+                    snapshot.name, name
+                );
+                continue;
+            }
+            let backups = self.settings.backups.as_deref();
+            if !has_backups(snapshot, backups) {
+                info!(
+                    "Snapshot '{}' has none of the backups sent to remote '{}'.",
+                    snapshot.name, name
+                );
+                continue;
+            }
+            if archive::partial_marker(&snapshot.path).exists() {
+                warning!(
+                    "Snapshot '{}' is partial: some backups failed or are missing.",
+                    snapshot.name
+                );
+            }
+            let archive = Archive {
+                snapshot,
+                compression: &compression,
+                key: key.as_ref(),
+                backups,
+            };
+
+            let mut volumes = None;
+            let sha256 = match (&staging, volume_size) {
+                (_, Some(volume_size)) => {
+                    let dir = resume_dir(&upload, name)?;
+                    info!(
+                        "Sending snapshot '{}' to remote '{}' in volumes of {}.",
+                        snapshot.name,
+                        name,
+                        ByteSize(volume_size)
+                    );
+                    let (count, sha256) =
+                        send_volumes(&archive, &dir, volume_size, target, &file_name)?;
+                    volumes = Some(count);
+                    sha256
+                }
+                (None, None) => {
+                    info!(
+                        "Streaming snapshot '{}' to remote '{}'.",
+                        snapshot.name, name
+                    );
+                    let remote_path = rclone::join(target, &file_name);
+                    let sha256 = stream_snapshot(&archive, &remote_path)?;
+                    verify_upload(&remote_path, &sha256)?;
+                    sha256
+                }
+                (Some(staging), None) => {
+                    check_staging_space(snapshot, staging.path(), None, 0)?;
+
+                    // Comprime o snapshot
+                    info!("Compressing snapshot '{}'.", snapshot.name);
+                    let compressed_filepath = staging.path().join(&file_name);
+                    let sha256 = compress_snapshot(&archive, &compressed_filepath)?;
+
+                    // Envia o arquivo para o remote
+                    info!("Sending snapshot '{}' to remote '{}'.", snapshot.name, name);
+                    let remote_path = rclone::join(target, &file_name);
+                    rclone::copy_to(&compressed_filepath, &remote_path)?;
+                    fs::remove_file(&compressed_filepath).ok();
+                    verify_upload(&remote_path, &sha256)?;
+                    sha256
+                }
+            };
+
+            self.record(Upload {
+                snapshot: snapshot.name.clone(),
+                file: file_name,
+                uploaded_at: Local::now().format(ledger::TIMESTAMP_FORMAT).to_string(),
+                volumes,
+                sha256: Some(sha256),
+                verified: true,
+            })?;
+            sent += 1;
+        }
+        Ok(Outcome::Sent(sent))
+    }
+
+    /// Checa se o último envio ao remote foi feito há menos tempo que a
+    /// frequência configurada
+    fn is_recent(&self, ledger: &Ledger) -> bool {
+        let Some(frequency) = self.settings.frequency else {
+            return false;
+        };
+        let Some(last) = ledger
+            .uploads(&self.name)
+            .iter()
+            .filter_map(Upload::time)
+            .max()
+        else {
+            return false;
+        };
+        let elapsed = (Local::now().naive_local() - last)
+            .to_std()
+            .unwrap_or_default();
+        if elapsed >= frequency.0 {
+            return false;
+        }
         info!(
-            "Testing conection with remote drive `{}`.",
-            &config.rclone_remote
+            "Skipping remote '{}': the last upload was at {} (frequency: {}).",
+            self.name,
+            last.format(ledger::TIMESTAMP_FORMAT),
+            frequency
         );
-        if process::Command::new("rclone")
-            .stderr(Stdio::null())
-            .current_dir(&config.archive_path)
-            .args(["sync", "--dry-run", "latest/", &config.rclone_remote])
-            .status()
-            .is_err()
-        {
-            return Err(Box::new(ErrInacessibleRemote));
-        }
-
-        // Comprime o backup
-        info!("Compressing backup data");
-        let today = Utc::today();
-        let backup_file_name = format!(
-            "backy_{}-{}-{}.tar.gz",
-            &today.year(),
-            &today.month(),
-            &today.day()
+        true
+    }
+
+    /// Registra um envio bem-sucedido no registro de envios
+    fn record(&self, upload: Upload) -> BackyResult<()> {
+        let _guard = self
+            .ledger_lock
+            .lock()
+            .unwrap_or_else(|err| err.into_inner());
+        let mut ledger = Ledger::load(&self.archive_path)?;
+        ledger.record(&self.name, upload);
+        ledger.save(&self.archive_path)
+    }
+}
+
+/// Um arquivo comprimido de um snapshot, como ele é enviado a um remote
+struct Archive<'a> {
+    snapshot: &'a Snapshot,
+    compression: &'a CompressionSettings,
+    /// Chave usada para criptografar o arquivo, se houver
+    key: Option<&'a Key>,
+    /// Backups incluídos no arquivo. Sem a lista, inclui todos.
+    backups: Option<&'a [String]>,
+}
+
+impl Archive<'_> {
+    /// Escreve o arquivo comprimido em `out`, criptografado se houver uma chave
+    fn write<W: Write>(&self, out: W) -> BackyResult<W> {
+        let (snapshot, compression) = (self.snapshot, self.compression);
+        match self.key {
+            Some(key) => {
+                let encrypted = crypt::encrypt(key, out)?;
+                let encrypted =
+                    pack::write_snapshot(snapshot, compression, self.backups, encrypted)?;
+                encrypted.finish().map_err(|err| {
+                    Box::new(ErrCompressionFailed {
+                        target: snapshot.name.clone(),
+                        err,
+                    }) as Box<dyn BackyError>
+                })
+            }
+            None => pack::write_snapshot(snapshot, compression, self.backups, out),
+        }
+    }
+}
+
+/// Checa se o snapshot tem algum dos backups escolhidos
+pub(super) fn has_backups(snapshot: &Snapshot, backups: Option<&[String]>) -> bool {
+    match backups {
+        Some(backups) => backups
+            .iter()
+            .any(|backup| snapshot.path.join(backup).is_dir()),
+        None => true,
+    }
+}
+
+/// Nome do arquivo comprimido de um snapshot no remote
+fn archive_file_name(snapshot: &Snapshot, format: CompressionFormat, encrypted: bool) -> String {
+    let name = format!(
+        "backy_{}.{}",
+        snapshot.date.format("%Y-%m-%d"),
+        pack::extension(format)
+    );
+    match encrypted {
+        true => format!("{}.{}", name, crypt::EXTENSION),
+        false => name,
+    }
+}
+
+/// Grava o snapshot comprimido em `path`, devolvendo o SHA-256 do arquivo
+fn compress_snapshot(archive: &Archive, path: &Path) -> BackyResult<String> {
+    let fail = |err: io::Error| -> Box<dyn BackyError> {
+        Box::new(ErrCompressionFailed {
+            target: path.display().to_string(),
+            err,
+        })
+    };
+    let file = BufWriter::new(File::create(path).map_err(fail)?);
+    let (mut out, sha256) = archive.write(Sha256Writer::new(file))?.finish();
+    out.flush().map_err(fail)?;
+    Ok(sha256)
+}
+
+/// Envia o snapshot comprimido para `target` no remote enquanto ele é gerado,
+/// pela entrada padrão do `rclone rcat`. Devolve o SHA-256 do que foi enviado.
+fn stream_snapshot(archive: &Archive, target: &str) -> BackyResult<String> {
+    let fail = |err| Box::new(ErrSendRemoteFail { err }) as Box<dyn BackyError>;
+    let mut rclone = Process::new("rclone")
+        .arg("rcat")
+        .arg(target)
+        .spawn()
+        .map_err(fail)?;
+    // NOTE: a entrada do rclone fica com esta função (e não com o arquivo),
+    // para que ela só seja fechada depois de o rclone ser interrompido. Em caso
+    // de erro, o tar e o compressor terminam o arquivo ao serem descartados, e
+    // o rclone enviaria um arquivo bem formado, mas incompleto, se visse o fim
+    // da entrada.
+    let mut stdin = BufWriter::new(rclone.stdin());
+    let written = archive
+        .write(Sha256Writer::new(&mut stdin))
+        .map(|out| out.finish().1)
+        .and_then(|sha256| {
+            stdin.flush().map(|_| sha256).map_err(|err| {
+                Box::new(ErrCompressionFailed {
+                    target: target.to_string(),
+                    err,
+                }) as Box<dyn BackyError>
+            })
+        });
+    let sha256 = match written {
+        Ok(sha256) => sha256,
+        Err(err) => {
+            // NOTE: se o rclone já terminou com erro, a escrita falhou por
+            // causa dele
+            if rclone.has_failed() {
+                drop(stdin);
+                return Err(rclone.wait().err().map_or(err, fail));
+            }
+            rclone.kill();
+            drop(stdin);
+            warning!(
+                "The upload of '{}' was interrupted. An incomplete file may be left on the remote.",
+                target
+            );
+            return Err(err);
+        }
+    };
+    drop(stdin);
+    rclone.wait().map(|_| sha256).map_err(fail)
+}
+
+/// Envia o snapshot comprimido em volumes de `volume_size` bytes, precedidos do
+/// índice com o SHA-256 de cada um. Devolve o número de volumes e o SHA-256 do
+/// arquivo inteiro (dos volumes em sequência).
+///
+/// Os volumes são gerados um por vez em `dir` e apagados depois de conferidos
+/// no remote. Se um volume não puder ser enviado, o resto do arquivo continua
+/// sendo gravado em `dir` (se houver espaço para ele), junto com o índice, e a
+/// próxima execução envia o que falta a partir desses arquivos. Gerar o arquivo
+/// de novo não serve: a chave do age e os metadados do tar mudam a cada vez, e
+/// os volumes já enviados não combinariam com os novos.
+fn send_volumes(
+    archive: &Archive,
+    dir: &Path,
+    volume_size: u64,
+    remote: &str,
+    file_name: &str,
+) -> BackyResult<(usize, String)> {
+    let staged = StagedVolumes::new(dir, file_name);
+    if staged.is_resumable() {
+        info!(
+            "Resuming the interrupted upload of '{}' from '{}'.",
+            file_name,
+            dir.display()
         );
-        let temporary_dir = tempdir().unwrap();
-        let compressed_filepath = temporary_dir.path().join(backup_file_name);
-        if process::Command::new("tar")
-            .current_dir(&config.archive_path)
-            .stdout(Stdio::null())
-            .arg("-vczpf")
-            .arg(&compressed_filepath)
-            .arg("./")
-            .status()
-            .is_err()
-        {
-            return Err(Box::new(ErrCompressionFailed));
+        return staged.send(remote, true);
+    }
+    staged.discard();
+    check_staging_space(archive.snapshot, dir, Some(volume_size), 0)?;
+
+    let mut failure = None;
+    let mut out_of_space = false;
+    let mut sizes = Vec::new();
+    let mut writer = VolumeWriter::new(dir, file_name, volume_size, |volume| {
+        sizes.push((volume.name.clone(), volume.size));
+        if failure.is_some() {
+            return Ok(());
+        }
+        match send_volume(remote, volume) {
+            Ok(()) => {
+                fs::remove_file(&volume.path).ok();
+                Ok(())
+            }
+            Err(err) => {
+                failure = Some(err);
+                // NOTE: depois de uma falha, todo o resto do arquivo fica no
+                // disco. Sem espaço para ele, a geração é interrompida e o envio
+                // recomeça do zero na próxima execução.
+                let written = sizes.iter().map(|(_, size)| size).sum();
+                if let Err(err) = check_staging_space(archive.snapshot, dir, None, written) {
+                    warning!(
+                        "Unable to keep the rest of '{}' for the next run: {}. The upload will start over in the next run.",
+                        file_name,
+                        err.get_err_msg()
+                    );
+                    out_of_space = true;
+                    return Err(err);
+                }
+                warning!(
+                    "The remaining volumes of '{}' will be kept in '{}' and sent by the next run.",
+                    file_name,
+                    dir.display()
+                );
+                Ok(())
+            }
+        }
+    });
+    let written = archive
+        .write(Sha256Writer::new(&mut writer))
+        .map(|out| out.finish().1)
+        .and_then(|sha256| Ok((writer.finish()?, sha256)));
+    let saved = written.and_then(|(index, sha256)| staged.save(&index, &sha256, &sizes));
+    if let Err(err) = saved {
+        staged.discard();
+        return Err(match failure {
+            Some(failure) if out_of_space => failure,
+            _ => err,
+        });
+    }
+    match failure {
+        Some(err) => Err(err),
+        None => staged.send(remote, false),
+    }
+}
+
+/// Volumes de um arquivo gravados em disco, com o índice e o estado do envio
+struct StagedVolumes {
+    dir: PathBuf,
+    file_name: String,
+}
+
+impl StagedVolumes {
+    fn new(dir: &Path, file_name: &str) -> Self {
+        Self {
+            dir: dir.to_owned(),
+            file_name: file_name.to_string(),
+        }
+    }
+
+    fn index_name(&self) -> String {
+        format!("{}.{}", self.file_name, volumes::INDEX_EXTENSION)
+    }
+
+    /// Arquivo com o SHA-256 do arquivo inteiro, seguido do tamanho de cada
+    /// volume (`<tamanho>  <volume>`), que não estão no índice
+    fn state_path(&self) -> PathBuf {
+        self.dir
+            .join(format!("{}.{}", self.file_name, RESUME_EXTENSION))
+    }
+
+    /// Checa se um envio interrompido deixou o índice e o estado gravados
+    fn is_resumable(&self) -> bool {
+        self.dir.join(self.index_name()).exists() && self.state_path().exists()
+    }
+
+    /// Grava o índice e o estado, que tornam o envio retomável
+    fn save(&self, index: &str, sha256: &str, sizes: &[(String, u64)]) -> BackyResult<()> {
+        let mut state = format!("{}\n", sha256);
+        for (name, size) in sizes {
+            state.push_str(&format!("{}  {}\n", size, name));
         }
+        self.write(&self.dir.join(self.index_name()), index)?;
+        self.write(&self.state_path(), &state)
+    }
+
+    /// Envia o índice e depois os volumes que ainda estão no disco, apagando
+    /// cada um depois de conferido. Com `check_sent`, os volumes enviados por
+    /// uma execução anterior são conferidos no remote: primeiro o tamanho, que
+    /// é barato, e só então o SHA-256 registrado no índice.
+    fn send(&self, remote: &str, check_sent: bool) -> BackyResult<(usize, String)> {
+        let index_path = self.dir.join(self.index_name());
+        let index = self.read(&index_path)?;
+        let state = self.read(&self.state_path())?;
+        let mut lines = state.lines();
+        let sha256 = lines.next().unwrap_or_default().trim().to_string();
+        let sizes: Vec<(&str, u64)> = lines
+            .filter_map(|line| line.split_once("  "))
+            .filter_map(|(size, name)| Some((name, size.parse().ok()?)))
+            .collect();
 
-        // Sincroniza o backup com o remote
-        info!("Syncing data with remote");
-        if process::Command::new("rclone")
-            .arg("sync")
-            .arg("--progress")
-            .arg(&compressed_filepath)
-            .arg(&config.rclone_remote)
-            .status()
-            .is_err()
-        {
-            return Err(Box::new(ErrSendRemoteFail));
+        let index_target = rclone::join(remote, &self.index_name());
+        rclone::copy_to(&index_path, &index_target)?;
+        verify_upload(
+            &index_target,
+            &checksum::hex(Sha256::new_with_prefix(&index)),
+        )?;
+
+        let entries = volumes::parse_index(&index);
+        for (name, volume_sha256) in &entries {
+            let path = self.dir.join(name);
+            if let Ok(metadata) = fs::metadata(&path) {
+                let volume = Volume {
+                    name: name.clone(),
+                    path,
+                    size: metadata.len(),
+                    sha256: volume_sha256.clone(),
+                };
+                send_volume(remote, &volume)?;
+                fs::remove_file(&volume.path).ok();
+                continue;
+            }
+            if !check_sent {
+                continue;
+            }
+            let size = sizes.iter().find(|(sized, _)| sized == name);
+            let matches = size.is_some_and(|(_, size)| {
+                let volume = Volume {
+                    name: name.clone(),
+                    path,
+                    size: *size,
+                    sha256: volume_sha256.clone(),
+                };
+                volume_matches(&rclone::join(remote, name), &volume)
+            });
+            if !matches {
+                // NOTE: sem o volume, o que está no disco não serve mais, e a
+                // próxima execução gera o arquivo de novo
+                self.discard();
+                return Err(Box::new(ErrVolumeLost { name: name.clone() }));
+            }
+            info!("Volume '{}' is already on the remote.", name);
         }
+        self.discard();
+        fs::remove_dir(&self.dir).ok();
+        Ok((entries.len(), sha256))
+    }
+
+    /// Apaga os volumes, o índice e o estado
+    fn discard(&self) {
+        let prefix = format!("{}.", self.file_name);
+        if let Ok(entries) = fs::read_dir(&self.dir) {
+            for entry in entries.flatten() {
+                if entry.file_name().to_string_lossy().starts_with(&prefix) {
+                    fs::remove_file(entry.path()).ok();
+                }
+            }
+        }
+    }
 
-        Ok(())
+    fn read(&self, path: &Path) -> BackyResult<String> {
+        fs::read_to_string(path).map_err(|err| {
+            Box::new(ErrStaging {
+                path: path.to_owned(),
+                err,
+            }) as Box<dyn BackyError>
+        })
+    }
+
+    fn write(&self, path: &Path, content: &str) -> BackyResult<()> {
+        fs::write(path, content).map_err(|err| {
+            Box::new(ErrStaging {
+                path: path.to_owned(),
+                err,
+            }) as Box<dyn BackyError>
+        })
     }
 }
 
-// #######################
-//   Definições privadas
-// #######################
-/// Checa se o remote passado pelo usuário é um remote válido
-fn rclone_valid_remote(rclone_remote: &str) -> bool {
-    let listremotes_output = process::Command::new("rclone")
+/// Envia um volume e confere se ele chegou inteiro. Volumes que faltam ou que
+/// chegaram corrompidos são reenviados algumas vezes.
+fn send_volume(remote: &str, volume: &Volume) -> BackyResult<()> {
+    let target = rclone::join(remote, &volume.name);
+    for _ in 0..VOLUME_ATTEMPTS {
+        info!("Sending volume '{}'.", volume.name);
+        if let Err(err) = rclone::copy_to(&volume.path, &target) {
+            warning!("{}", err.get_err_msg());
+        } else if volume_matches(&target, volume) {
+            return Ok(());
+        } else {
+            warning!(
+                "Volume '{}' is missing or corrupted on the remote.",
+                volume.name
+            );
+        }
+    }
+    Err(Box::new(ErrVolumeUpload {
+        name: volume.name.clone(),
+    }))
+}
+
+/// Confere se o volume está no remote com o tamanho e o SHA-256 esperados. O
+/// SHA-256 (que pode exigir baixar o volume) só é calculado se o tamanho
+/// confere.
+fn volume_matches(target: &str, volume: &Volume) -> bool {
+    let Ok(entries) = rclone::list(target) else {
+        return false;
+    };
+    let same_size = entries
+        .iter()
+        .any(|entry| entry.name == volume.name && entry.size == volume.size as i64);
+    same_size && rclone::sha256(target).is_ok_and(|hash| hash == volume.sha256)
+}
+
+/// Confere se o arquivo enviado para `target` tem o SHA-256 calculado durante a
+/// escrita. Um arquivo diferente no remote é um erro.
+fn verify_upload(target: &str, expected: &str) -> BackyResult<()> {
+    info!("Verifying '{}'.", target);
+    let found = rclone::sha256(target)?;
+    match found == expected {
+        true => Ok(()),
+        false => Err(Box::new(ErrVerifyMismatch {
+            target: target.to_string(),
+            expected: expected.to_string(),
+            found,
+        })),
+    }
+}
+
+/// Diretório onde os volumes enviados a `remote` são gravados. Ele não é
+/// apagado no fim da execução, para que um envio interrompido possa ser
+/// retomado.
+fn resume_dir(upload: &UploadSettings, remote: &str) -> BackyResult<PathBuf> {
+    let parent = upload.staging_dir.clone().unwrap_or_else(env::temp_dir);
+    let dir = parent.join(format!("backy-volumes-{}", remote));
+    fs::create_dir_all(&dir).map_err(|err| {
+        Box::new(ErrStaging {
+            path: dir.clone(),
+            err,
+        }) as Box<dyn BackyError>
+    })?;
+    Ok(dir)
+}
+
+/// Cria o diretório temporário onde os arquivos são gravados antes do envio
+fn staging_dir(upload: &UploadSettings) -> BackyResult<TempDir> {
+    let parent = upload.staging_dir.clone().unwrap_or_else(env::temp_dir);
+    Builder::new()
+        .prefix("backy-")
+        .tempdir_in(&parent)
+        .map_err(|err| Box::new(ErrStaging { path: parent, err }) as Box<dyn BackyError>)
+}
+
+/// Confere se há espaço no diretório temporário para um volume ou, sem volumes,
+/// para o que falta do arquivo do snapshot depois dos `written` bytes já
+/// gravados. Como o tamanho comprimido só é conhecido depois, usa o tamanho
+/// original como limite.
+fn check_staging_space(
+    snapshot: &Snapshot,
+    staging: &Path,
+    volume_size: Option<u64>,
+    written: u64,
+) -> BackyResult<()> {
+    let fail = |path: &Path, err: io::Error| -> Box<dyn BackyError> {
+        Box::new(ErrStaging {
+            path: path.to_owned(),
+            err,
+        })
+    };
+    let needed = match volume_size {
+        Some(size) => size,
+        None => archive::content_size(&snapshot.path)
+            .map_err(|err| fail(&snapshot.path, err))?
+            .saturating_sub(written),
+    };
+    let space = archive::disk_space(staging).map_err(|err| fail(staging, err))?;
+    if needed > space.available {
+        return Err(Box::new(ErrNoStagingSpace {
+            snapshot: snapshot.name.clone(),
+            path: staging.parent().unwrap_or(staging).to_owned(),
+            needed: ByteSize(needed),
+            available: ByteSize(space.available),
+        }));
+    }
+    Ok(())
+}
+
+/// Interpreta uma data no formato dos snapshots (`20240131`) ou ISO
+/// (`2024-01-31`)
+fn parse_date(raw: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(raw, archive::SNAPSHOT_FORMAT)
+        .or_else(|_| NaiveDate::parse_from_str(raw, "%Y-%m-%d"))
+        .ok()
+}
+
+/// Checa se o destino passado pelo usuário usa um remote válido
+fn rclone_valid_remote(target: &str) -> BackyResult<bool> {
+    let Some((remote, _)) = target.split_once(':') else {
+        return Ok(false);
+    };
+    let output = Process::new("rclone")
         .arg("listremotes")
-        .output()
-        .unwrap();
-    let remotes = str::from_utf8(&listremotes_output.stdout).unwrap();
-    return remotes.lines().any(|remote| rclone_remote.eq(remote));
+        .run()
+        .map_err(|err| Box::new(err) as Box<dyn BackyError>)?;
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .any(|line| line.strip_suffix(':') == Some(remote)))
 }
 
 // #######################
@@ -100,35 +875,175 @@ fn rclone_valid_remote(rclone_remote: &str) -> bool {
 // #######################
 /// Erro lançado quando não é possível enviar os arquivos de backup para o drive
 /// remoto
-struct ErrSendRemoteFail;
+struct ErrSendRemoteFail {
+    err: ErrProcess,
+}
 impl BackyError for ErrSendRemoteFail {
     fn get_err_msg(&self) -> String {
-        "unable to send compressed backup to rclone remote".into()
+        format!(
+            "unable to send compressed backup to rclone remote:\n{}",
+            self.err.get_err_msg()
+        )
     }
 }
 
 /// Erro lançado quando o nome do remote não consta na lista de remotes válidos
 /// do `rclone`
-struct ErrBadRemoteName;
+struct ErrBadRemoteName {
+    target: String,
+}
 impl BackyError for ErrBadRemoteName {
     fn get_err_msg(&self) -> String {
-        "invalid rclone_remote setting in config. Run `rclone listremotes` for a list of possible values".into()
+        format!(
+            "invalid rclone target '{}' in config. Run `rclone listremotes` for a list of possible values",
+            self.target
+        )
+    }
+}
+
+/// Erro lançado quando a thread de envio a um remote é interrompida por um
+/// pânico
+struct ErrRemoteThread {
+    remote: String,
+}
+impl BackyError for ErrRemoteThread {
+    fn get_err_msg(&self) -> String {
+        format!("the upload to remote '{}' crashed", self.remote)
     }
 }
 
 /// Erro lançado quando não é possível criar uma conexão com o remote
-struct ErrInacessibleRemote;
+struct ErrInacessibleRemote {
+    err: ErrProcess,
+}
 impl BackyError for ErrInacessibleRemote {
     fn get_err_msg(&self) -> String {
-        "unable to establish connection with remote. Check your internet connection".into()
+        format!(
+            "unable to establish connection with remote. Check your internet connection:\n{}",
+            self.err.get_err_msg()
+        )
+    }
+}
+
+/// Erro lançado quando a seleção de snapshots passada pelo usuário é inválida
+struct ErrBadSelection {
+    arg: String,
+}
+impl BackyError for ErrBadSelection {
+    fn get_err_msg(&self) -> String {
+        format!(
+            "invalid snapshot selection at '{}'. Use snapshot names or one of --last N, --since DATE or --all",
+            self.arg
+        )
+    }
+}
+
+/// Erro lançado quando nenhum snapshot corresponde à seleção do usuário
+struct ErrNothingSelected;
+impl BackyError for ErrNothingSelected {
+    fn get_err_msg(&self) -> String {
+        "no snapshot matches the selection".into()
     }
 }
 
-/// Erro lançado quando não é possível comprimir o backup em um arquivo usando o
-/// comando tar
-struct ErrCompressionFailed;
+/// Erro lançado quando o envio seria feito sem criptografia, sem que o usuário
+/// tenha permitido
+struct ErrUnencrypted;
+impl BackyError for ErrUnencrypted {
+    fn get_err_msg(&self) -> String {
+        "refusing to send unencrypted snapshots. Configure the [encryption] section (globally or for the remote) or set allow_unencrypted = true".into()
+    }
+}
+
+/// Erro lançado quando não é possível gravar o arquivo comprimido
+struct ErrCompressionFailed {
+    /// Arquivo ou destino no remote
+    target: String,
+    err: io::Error,
+}
 impl BackyError for ErrCompressionFailed {
     fn get_err_msg(&self) -> String {
-        "there was an unexpected error while generating the compressed backup file".into()
+        format!(
+            "unable to write compressed backup '{}':\n{}",
+            self.target, self.err
+        )
+    }
+}
+
+/// Erro lançado quando não é possível usar o diretório temporário dos arquivos
+/// enviados
+struct ErrStaging {
+    path: PathBuf,
+    err: io::Error,
+}
+impl BackyError for ErrStaging {
+    fn get_err_msg(&self) -> String {
+        format!(
+            "unable to prepare staging directory '{}':\n{}",
+            self.path.display(),
+            self.err
+        )
+    }
+}
+
+/// Erro lançado quando um volume não chega inteiro ao remote
+struct ErrVolumeUpload {
+    name: String,
+}
+impl BackyError for ErrVolumeUpload {
+    fn get_err_msg(&self) -> String {
+        format!(
+            "unable to send volume '{}' after {} attempts. Run the command again to send the missing volumes",
+            self.name, VOLUME_ATTEMPTS
+        )
+    }
+}
+
+/// Erro lançado quando um volume enviado por uma execução anterior não está
+/// mais no remote
+struct ErrVolumeLost {
+    name: String,
+}
+impl BackyError for ErrVolumeLost {
+    fn get_err_msg(&self) -> String {
+        format!(
+            "volume '{}' is missing or corrupted on the remote and can't be sent again. Run the command again to send the whole snapshot",
+            self.name
+        )
+    }
+}
+
+/// Erro lançado quando o diretório temporário não tem espaço para o arquivo do
+/// snapshot
+struct ErrNoStagingSpace {
+    snapshot: String,
+    path: PathBuf,
+    needed: ByteSize,
+    available: ByteSize,
+}
+impl BackyError for ErrNoStagingSpace {
+    fn get_err_msg(&self) -> String {
+        format!(
+            "not enough space in '{}' for snapshot '{}' (it may need up to {}, but only {} are free). Set another staging_dir or use mode = \"stream\" in the [upload] section",
+            self.path.display(),
+            self.snapshot,
+            self.needed,
+            self.available
+        )
+    }
+}
+
+/// Erro lançado quando o arquivo no remote não tem o SHA-256 do que foi enviado
+struct ErrVerifyMismatch {
+    target: String,
+    expected: String,
+    found: String,
+}
+impl BackyError for ErrVerifyMismatch {
+    fn get_err_msg(&self) -> String {
+        format!(
+            "the upload of '{}' is corrupted: expected SHA-256 {}, found {}",
+            self.target, self.expected, self.found
+        )
     }
 }
@@ -0,0 +1,406 @@
+use super::{BackyCommand, ErrUnexpectedArg};
+
+use crate::{
+    archive::{self, Snapshot},
+    config::Config,
+    engine,
+    error::{BackyError, BackyResult},
+    ledger,
+    logging::{info, log, warning},
+    manifest::Manifest,
+    pins,
+};
+
+use std::{
+    collections::HashSet,
+    fs,
+    io::{self, BufRead, Write},
+    path::{Path, PathBuf},
+};
+
+// #######################
+//   Definições públicas
+// #######################
+/// Procura problemas no arquivo de backups deixados por execuções interrompidas
+/// ou por mudanças manuais, e oferece a correção de cada um.
+pub struct CmdRepair {
+    /// Aplica todas as correções sem perguntar
+    yes: bool,
+}
+
+impl CmdRepair {
+    pub fn from_args(args: &[String]) -> BackyResult<Self> {
+        let mut yes = false;
+        for arg in args {
+            match arg.as_str() {
+                "--yes" | "-y" => yes = true,
+                _ => return Err(Box::new(ErrUnexpectedArg { arg: arg.clone() })),
+            }
+        }
+        Ok(Self { yes })
+    }
+}
+
+impl BackyCommand for CmdRepair {
+    fn execute(&self, config: Config) -> BackyResult<()> {
+        archive::check_destination(&config)?;
+        let mut report = Report::default();
+
+        let snapshots = archive::list_snapshots(&config.archive_path)?;
+        // NOTE: as sobras são tratadas antes, já que recuperar a cópia anterior
+        // de um backup pode completar um snapshot
+        for problem in find_stray_entries(&config.archive_path, &snapshots)? {
+            self.handle(problem, &mut report);
+        }
+        for problem in find_incomplete_snapshots(&config, &snapshots) {
+            self.handle(problem, &mut report);
+        }
+
+        // NOTE: o `latest` é checado por último, já que as correções anteriores
+        // podem ter removido o snapshot para onde ele apontava
+        let snapshots = archive::list_snapshots(&config.archive_path)?;
+        if let Some(problem) = check_latest(&config.archive_path, &snapshots) {
+            self.handle(problem, &mut report);
+        }
+
+        if report.found == 0 {
+            info!("No problems found in the archive.");
+            return Ok(());
+        }
+        info!("Found {} problem(s), fixed {}.", report.found, report.fixed);
+        if !report.failures.is_empty() {
+            return Err(Box::new(ErrRepairFailed {
+                failures: report.failures,
+            }));
+        }
+        Ok(())
+    }
+}
+
+// #######################
+//   Definições privadas
+// #######################
+/// Um problema encontrado no arquivo de backups, com a correção proposta
+struct Problem {
+    description: String,
+    /// Sem correção, o problema é apenas mostrado e cabe ao usuário decidir o
+    /// que fazer
+    fix: Option<Fix>,
+}
+
+/// Correções que o `repair` sabe aplicar
+enum Fix {
+    /// Remove uma entrada que não pertence ao arquivo de backups
+    RemoveEntry(PathBuf),
+    /// Devolve uma entrada ao lugar de onde foi movida
+    MoveEntry(PathBuf, PathBuf),
+    /// Remove um snapshot inteiro
+    RemoveSnapshot(Snapshot),
+    /// Marca o snapshot como parcial, registrando os backups que faltam
+    MarkPartial(PathBuf, Vec<(String, String)>),
+    /// Aponta o `latest` para o snapshot informado, ou o remove se não houver
+    /// nenhum snapshot
+    PointLatest(PathBuf, Option<Snapshot>),
+}
+
+impl Fix {
+    fn describe(&self) -> String {
+        match self {
+            Fix::RemoveEntry(path) => format!("remove '{}'", path.display()),
+            Fix::MoveEntry(from, to) => {
+                format!("move '{}' back to '{}'", from.display(), to.display())
+            }
+            Fix::RemoveSnapshot(snapshot) => format!("remove snapshot '{}'", snapshot.name),
+            Fix::MarkPartial(path, _) => format!("mark '{}' as partial", path.display()),
+            Fix::PointLatest(_, Some(snapshot)) => {
+                format!("point `latest` to '{}'", snapshot.name)
+            }
+            Fix::PointLatest(_, None) => "remove `latest`".into(),
+        }
+    }
+
+    fn apply(&self) -> io::Result<()> {
+        match self {
+            Fix::RemoveEntry(path) => match fs::symlink_metadata(path)?.is_dir() {
+                true => fs::remove_dir_all(path),
+                false => fs::remove_file(path),
+            },
+            Fix::MoveEntry(from, to) => fs::rename(from, to),
+            Fix::RemoveSnapshot(snapshot) => archive::delete_snapshot(snapshot),
+            Fix::MarkPartial(path, missing) => archive::mark_partial(path, missing),
+            Fix::PointLatest(link, Some(snapshot)) => archive::point_latest(link, &snapshot.path),
+            Fix::PointLatest(link, None) => fs::remove_file(link),
+        }
+    }
+}
+
+/// Contagem dos problemas encontrados e corrigidos
+#[derive(Default)]
+struct Report {
+    found: usize,
+    fixed: usize,
+    /// Correções que falharam e o motivo
+    failures: Vec<(String, String)>,
+}
+
+impl CmdRepair {
+    /// Mostra um problema e aplica a correção, se o usuário concordar
+    fn handle(&self, problem: Problem, report: &mut Report) {
+        report.found += 1;
+        warning!("{}", problem.description);
+        let Some(fix) = problem.fix else {
+            info!("Not fixed automatically. Check it and remove it by hand if it isn't needed.");
+            return;
+        };
+        let describe = fix.describe();
+        if !self.yes && !confirm(&format!("Fix: {}?", describe)) {
+            info!("Skipped.");
+            return;
+        }
+        match fix.apply() {
+            Ok(()) => {
+                info!("Done: {}.", describe);
+                report.fixed += 1;
+            }
+            Err(err) => report.failures.push((describe, err.to_string())),
+        }
+    }
+}
+
+/// Pergunta ao usuário se uma ação deve ser executada. Sem resposta (ex: se a
+/// entrada padrão não for um terminal), a ação não é executada.
+fn confirm(question: &str) -> bool {
+    print!("{} [y/N] ", question);
+    io::stdout().flush().ok();
+    let mut answer = String::new();
+    if io::stdin().lock().read_line(&mut answer).is_err() {
+        return false;
+    }
+    matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes")
+}
+
+/// Procura entradas na raíz do arquivo e dos snapshots que não foram criadas
+/// pelo backy, ou que sobraram de execuções interrompidas. Só as sobras do
+/// backy podem ser removidas; as demais entradas são apenas mostradas.
+fn find_stray_entries(archive_path: &Path, snapshots: &[Snapshot]) -> BackyResult<Vec<Problem>> {
+    let entries = read_dir_names(archive_path).map_err(|err| {
+        Box::new(ErrRepairRead {
+            path: archive_path.to_owned(),
+            err,
+        }) as Box<dyn BackyError>
+    })?;
+    let snapshot_names: HashSet<&str> = snapshots.iter().map(|s| s.name.as_str()).collect();
+    let known = [
+        archive::LATEST_LINK,
+        archive::ARCHIVE_MARKER,
+        pins::PINS_FILE,
+        ledger::LEDGER_FILE,
+        // NOTE: criado pelo `mkfs` quando o arquivo fica na raíz de um disco
+        "lost+found",
+    ];
+
+    let mut problems = Vec::new();
+    for name in entries {
+        if snapshot_names.contains(name.as_str()) || known.contains(&name.as_str()) {
+            continue;
+        }
+        let path = archive_path.join(&name);
+        let (reason, fix) = if name.starts_with(archive::TRASH_PREFIX) {
+            (
+                "is a snapshot whose removal was interrupted",
+                Some(Fix::RemoveEntry(path.clone())),
+            )
+        } else if name.ends_with(".tmp") {
+            (
+                "is a temporary file left by an interrupted run",
+                Some(Fix::RemoveEntry(path.clone())),
+            )
+        } else {
+            ("is not a snapshot", None)
+        };
+        problems.push(Problem {
+            description: format!("'{}' {}.", path.display(), reason),
+            fix,
+        });
+    }
+
+    for snapshot in snapshots {
+        // NOTE: apenas as sobras do motor embutido são tratadas aqui. Arquivos
+        // desconhecidos dentro de um snapshot podem ser do usuário.
+        let names = read_dir_names(&snapshot.path).unwrap_or_default();
+        for name in names
+            .iter()
+            .filter(|n| n.starts_with(engine::PREVIOUS_PREFIX))
+        {
+            let path = snapshot.path.join(name);
+            let backup = snapshot.path.join(&name[engine::PREVIOUS_PREFIX.len()..]);
+            // NOTE: sem o backup, a cópia anterior é a única que sobrou
+            let fix = match backup.exists() {
+                true => Fix::RemoveEntry(path.clone()),
+                false => Fix::MoveEntry(path.clone(), backup),
+            };
+            problems.push(Problem {
+                description: format!(
+                    "'{}' is a leftover copy from an interrupted backup.",
+                    path.display()
+                ),
+                fix: Some(fix),
+            });
+        }
+    }
+    Ok(problems)
+}
+
+/// Procura snapshots em que faltam backups: backups com manifesto mas sem
+/// diretório, backups configurados que já existiam em snapshots anteriores,
+/// e diretórios sem manifesto em snapshots que têm manifestos
+fn find_incomplete_snapshots(config: &Config, snapshots: &[Snapshot]) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    let mut seen: HashSet<String> = HashSet::new();
+    for snapshot in snapshots {
+        let present: Vec<String> = read_dir_names(&snapshot.path)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|name| !name.starts_with('.') && snapshot.path.join(name).is_dir())
+            .collect();
+        if present.is_empty() {
+            problems.push(Problem {
+                description: format!("Snapshot '{}' contains no backups.", snapshot.name),
+                fix: Some(Fix::RemoveSnapshot(snapshot.clone())),
+            });
+            continue;
+        }
+
+        let recorded = Manifest::recorded_backups(&snapshot.path);
+        let mut missing = Vec::new();
+        for name in &recorded {
+            if !present.contains(name) {
+                missing.push((name.clone(), "has a manifest but no directory".to_string()));
+            }
+        }
+        for name in config.backups.keys() {
+            if seen.contains(name) && !present.contains(name) && !recorded.contains(name) {
+                missing.push((name.clone(), "is missing from the snapshot".to_string()));
+            }
+        }
+        if !recorded.is_empty() {
+            for name in &present {
+                if !recorded.contains(name) {
+                    missing.push((name.clone(), "has no manifest (interrupted?)".to_string()));
+                }
+            }
+        }
+        seen.extend(present);
+
+        if missing.is_empty() || archive::partial_marker(&snapshot.path).exists() {
+            continue;
+        }
+        let details: Vec<String> = missing
+            .iter()
+            .map(|(name, reason)| format!("'{}' {}", name, reason))
+            .collect();
+        problems.push(Problem {
+            description: format!(
+                "Snapshot '{}' is incomplete: {}.",
+                snapshot.name,
+                details.join(", ")
+            ),
+            fix: Some(Fix::MarkPartial(snapshot.path.clone(), missing)),
+        });
+    }
+    problems
+}
+
+/// Confere se o `latest` aponta para o snapshot completo mais recente
+fn check_latest(archive_path: &Path, snapshots: &[Snapshot]) -> Option<Problem> {
+    let link = archive_path.join(archive::LATEST_LINK);
+    let expected = snapshots
+        .iter()
+        .rev()
+        .find(|snapshot| !archive::partial_marker(&snapshot.path).exists())
+        .or(snapshots.last())
+        .cloned();
+
+    let target = match fs::symlink_metadata(&link) {
+        Err(_) => {
+            let snapshot = expected?;
+            return Some(Problem {
+                description: "`latest` link is missing.".into(),
+                fix: Some(Fix::PointLatest(link, Some(snapshot))),
+            });
+        }
+        Ok(meta) if !meta.file_type().is_symlink() => {
+            return Some(Problem {
+                description: format!("'{}' is not a symlink.", link.display()),
+                fix: Some(Fix::PointLatest(link, expected)),
+            });
+        }
+        Ok(_) => fs::read_link(&link).ok()?,
+    };
+
+    let resolved = archive_path.join(&target);
+    let description = match &expected {
+        _ if !resolved.is_dir() => format!(
+            "`latest` points to '{}', which doesn't exist.",
+            target.display()
+        ),
+        Some(snapshot)
+            if fs::canonicalize(&resolved).ok() != fs::canonicalize(&snapshot.path).ok() =>
+        {
+            format!(
+                "`latest` points to '{}', but the newest complete snapshot is '{}'.",
+                target.display(),
+                snapshot.name
+            )
+        }
+        _ => return None,
+    };
+    Some(Problem {
+        description,
+        fix: Some(Fix::PointLatest(link, expected)),
+    })
+}
+
+/// Nomes das entradas de um diretório
+fn read_dir_names(path: &Path) -> io::Result<Vec<String>> {
+    let mut names = Vec::new();
+    for entry in fs::read_dir(path)? {
+        names.push(entry?.file_name().to_string_lossy().into_owned());
+    }
+    names.sort();
+    Ok(names)
+}
+
+// #######################
+//         Erros
+// #######################
+/// Erro lançado quando não é possível ler o arquivo de backups
+struct ErrRepairRead {
+    path: PathBuf,
+    err: io::Error,
+}
+impl BackyError for ErrRepairRead {
+    fn get_err_msg(&self) -> String {
+        format!("unable to read '{}':\n{}", self.path.display(), self.err)
+    }
+}
+
+/// Erro lançado quando algumas correções não puderam ser aplicadas
+struct ErrRepairFailed {
+    /// Correções que falharam e o motivo
+    failures: Vec<(String, String)>,
+}
+impl BackyError for ErrRepairFailed {
+    fn get_err_msg(&self) -> String {
+        let failures: Vec<String> = self
+            .failures
+            .iter()
+            .map(|(fix, reason)| format!("  {}: {}", fix, reason))
+            .collect();
+        format!(
+            "unable to apply {} fix(es):\n{}",
+            self.failures.len(),
+            failures.join("\n")
+        )
+    }
+}
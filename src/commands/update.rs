@@ -1,20 +1,33 @@
 use chrono::Utc;
 
-use super::{user_has_rsync, BackyCommand, ErrNoRsync};
+use super::{clean, user_has_rsync, BackyCommand, ErrNoRsync};
 
 use crate::{
-    config::{BackupDescription, Config},
+    archive::{self, Snapshot},
+    cancel::{self, CancelToken},
+    config::{BackupDescription, BackupEngine, Config},
+    engine,
     error::{BackyError, BackyResult},
-    logging::{info, log},
+    guard,
+    logging::{info, log, warning},
+    manifest::Manifest,
+    progress::{ProgressHandle, ProgressView},
+    rsync::{self, RsyncOutcome},
+    runner,
+    transfer::{apply_policy, IssueKind, TransferIssues},
 };
 
 use std::{
-    fs, io,
-    os::unix::fs::symlink,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
     path::{Path, PathBuf},
     process,
-    sync::Arc,
-    thread,
+    sync::{
+        mpsc::{self, Receiver},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
 };
 
 // #######################
@@ -23,47 +36,188 @@ use std::{
 pub struct CmdUpdate;
 impl BackyCommand for CmdUpdate {
     fn execute(&self, config: Config) -> BackyResult<()> {
-        if !user_has_rsync() {
+        let needs_rsync = config
+            .backups
+            .values()
+            .any(|desc| desc.engine == BackupEngine::Rsync);
+        if needs_rsync && !user_has_rsync() {
             return Err(Box::new(ErrNoRsync));
         }
+        archive::check_destination(&config)?;
+        clean::prune_for_space(&config)?;
+
+        cancel::install_handler();
+        let run_deadline = config.run_timeout.map(|timeout| Instant::now() + timeout.0);
+
         // Cria o diretório do backup de hoje
         let backup_dir = Arc::new(create_backup_dir(&config.archive_path)?);
-        let latest_link = {
-            let mut latest_link = config.archive_path.clone();
-            latest_link.push("latest");
-            Arc::new(latest_link)
-        };
+        let archive_path = Arc::new(config.archive_path.clone());
 
         // Executa backups assíncronamente
-        let mut backup_handles = Vec::new();
-        for (name, desc) in config.backups {
+        let names: Vec<&str> = config.backups.keys().map(String::as_str).collect();
+        let (progress_view, progress_handles) = ProgressView::start(&names);
+        let (sender, receiver) = mpsc::channel();
+        let mut pending = Vec::new();
+        for ((name, desc), progress) in config.backups.into_iter().zip(progress_handles) {
             let backup_dir = backup_dir.clone();
-            let latest_link = latest_link.clone();
-            let backup_task =
-                thread::spawn(move || create_named_backup(&backup_dir, &latest_link, &name, &desc));
-            backup_handles.push(backup_task);
+            let archive_path = archive_path.clone();
+            let backup_deadline = desc.timeout.map(|timeout| Instant::now() + timeout.0);
+            let token = CancelToken::new(&[run_deadline, backup_deadline]);
+            let sender = sender.clone();
+            let task_name = name.clone();
+            let handle = thread::spawn(move || {
+                let limit = desc.link_dest_count.clamp(1, rsync::MAX_LINK_DESTS);
+                let previous =
+                    archive::previous_copies(&archive_path, &backup_dir, &task_name, limit);
+                let result = create_named_backup(
+                    &backup_dir,
+                    &previous,
+                    &task_name,
+                    &desc,
+                    progress,
+                    &token,
+                )
+                .and_then(|_| record_manifest(&backup_dir, previous.first(), &task_name));
+                progress.finish(result.is_ok());
+                sender.send((task_name, result)).ok();
+            });
+            pending.push(PendingBackup {
+                name,
+                handle,
+                token,
+                progress,
+                cancelled_at: None,
+            });
         }
+        drop(sender);
 
-        // Coleta erros caso encontre algum
-        for backup in backup_handles {
-            let result = backup.join().unwrap();
-            result?;
+        // Coleta os erros de todos os backups. Um backup que falha não impede
+        // que os demais terminem.
+        let results = collect_results(pending, &receiver);
+        progress_view.finish();
+        let mut failures = Vec::new();
+        for (name, result) in results {
+            if let Err(err) = result {
+                err.display();
+                failures.push((name, err.get_err_msg()));
+            }
         }
 
-        // Recria o link simbólico para latest
-        info!("Updating `latest` link.");
-        fs::remove_file(&*latest_link).ok();
-        if let Err(err) = symlink(&*backup_dir, &*latest_link) {
-            return Err(Box::new(ErrLatestUpdate { err }));
-        };
+        // Um snapshot incompleto é marcado como parcial, e não se torna o
+        // `latest`
+        if let Err(err) = archive::mark_partial(&backup_dir, &failures) {
+            return Err(Box::new(ErrPartialMark { err }));
+        }
+        if cancel::interrupted() {
+            return Err(Box::new(ErrInterrupted));
+        }
+        if !failures.is_empty() {
+            return Err(Box::new(ErrBackupsFailed {
+                names: failures.into_iter().map(|(name, _)| name).collect(),
+            }));
+        }
 
-        Ok(())
+        info!("Updating `latest` link.");
+        update_latest(&backup_dir, &archive_path.join(archive::LATEST_LINK))
     }
 }
 
 // #######################
 //   Definições privadas
 // #######################
+/// Tempo que um backup cancelado tem para terminar sozinho antes de ser
+/// abandonado. Dá ao rsync o tempo de ser terminado com SIGTERM e SIGKILL.
+const ABANDON_GRACE: Duration = Duration::from_secs(10);
+
+/// Intervalo entre verificações dos backups em andamento
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Um backup que ainda não entregou o seu resultado
+struct PendingBackup {
+    name: String,
+    handle: JoinHandle<()>,
+    token: CancelToken,
+    progress: ProgressHandle,
+    /// Quando o cancelamento do backup foi percebido
+    cancelled_at: Option<Instant>,
+}
+
+/// Espera o resultado de cada backup. Um backup que não termina depois de
+/// cancelado (por exemplo, travado em uma montagem de rede inacessível) é
+/// abandonado e contado como falha, sem que a sua thread seja esperada. Uma
+/// thread que entra em pânico também conta como falha do seu backup.
+fn collect_results(
+    mut pending: Vec<PendingBackup>,
+    receiver: &Receiver<(String, BackyResult<()>)>,
+) -> Vec<(String, BackyResult<()>)> {
+    let mut results = Vec::new();
+    while !pending.is_empty() {
+        if let Ok(message) = receiver.recv_timeout(POLL_INTERVAL) {
+            deliver(&mut pending, &mut results, message);
+            continue;
+        }
+
+        // NOTE: uma thread envia o resultado antes de terminar, então as que já
+        // tinham terminado antes de a fila ser esvaziada e não enviaram nada
+        // entraram em pânico
+        let finished: Vec<String> = pending
+            .iter()
+            .filter(|backup| backup.handle.is_finished())
+            .map(|backup| backup.name.clone())
+            .collect();
+        while let Ok(message) = receiver.try_recv() {
+            deliver(&mut pending, &mut results, message);
+        }
+        for name in finished {
+            let Some(idx) = pending.iter().position(|backup| backup.name == name) else {
+                continue;
+            };
+            let backup = pending.swap_remove(idx);
+            backup.handle.join().ok();
+            backup.progress.finish(false);
+            results.push((name.clone(), Err(Box::new(ErrBackupPanicked { name }))));
+        }
+
+        let now = Instant::now();
+        let mut idx = 0;
+        while idx < pending.len() {
+            let backup = &mut pending[idx];
+            let Some(reason) = backup.token.check() else {
+                idx += 1;
+                continue;
+            };
+            let cancelled_at = *backup.cancelled_at.get_or_insert(now);
+            if now - cancelled_at < ABANDON_GRACE {
+                idx += 1;
+                continue;
+            }
+            let backup = pending.swap_remove(idx);
+            warning!(
+                "'{}' backup didn't stop after being {}. It will be left running and counted as failed.",
+                backup.name,
+                reason
+            );
+            backup.progress.finish(false);
+            results.push((backup.name.clone(), Err(reason.into_error(&backup.name))));
+        }
+    }
+    results.sort_by(|a, b| a.0.cmp(&b.0));
+    results
+}
+
+/// Registra o resultado enviado por um backup e espera a sua thread, que já
+/// está terminando
+fn deliver(
+    pending: &mut Vec<PendingBackup>,
+    results: &mut Vec<(String, BackyResult<()>)>,
+    (name, result): (String, BackyResult<()>),
+) {
+    if let Some(idx) = pending.iter().position(|backup| backup.name == name) {
+        pending.swap_remove(idx).handle.join().ok();
+    }
+    results.push((name, result));
+}
+
 /// Cria um diretório para o backup.
 fn create_backup_dir(archive_path: &Path) -> BackyResult<PathBuf> {
     let today = Utc::today().format("%Y%m%d/").to_string();
@@ -75,6 +229,14 @@ fn create_backup_dir(archive_path: &Path) -> BackyResult<PathBuf> {
     }
 }
 
+/// Aponta o link `latest` para o snapshot recém criado
+fn update_latest(backup_dir: &Path, latest_link: &Path) -> BackyResult<()> {
+    match archive::point_latest(latest_link, backup_dir) {
+        Ok(()) => Ok(()),
+        Err(err) => Err(Box::new(ErrLatestUpdate { err })),
+    }
+}
+
 /// Gera a string que representa o diretório base do backup
 fn gen_backup_root_str(backup_root: &Path) -> BackyResult<String> {
     let mut backup_root = backup_root.to_owned();
@@ -84,45 +246,199 @@ fn gen_backup_root_str(backup_root: &Path) -> BackyResult<String> {
     // NOTE: isso garante que o path terá um '/' no final, o que impede que o
     // impede que o rsync crie um subdiretório acima do backup
     backup_root.push(PathBuf::from(""));
-    return Ok(format!("{}", backup_root.display()));
+    Ok(format!("{}", backup_root.display()))
 }
 
-/// Usa o rsync para gerar um backup nomeado na pasta de backups.
+/// Gera um backup nomeado na pasta de backups, usando o motor escolhido pelo
+/// usuário. Os arquivos inalterados são compartilhados com as cópias do backup
+/// nos snapshots `previous` (da mais recente para a mais antiga).
 fn create_named_backup(
     backup_dir: &Path,
-    latest_link: &Path,
+    previous: &[Snapshot],
     name: &str,
     desc: &BackupDescription,
+    progress: ProgressHandle,
+    token: &CancelToken,
 ) -> BackyResult<()> {
+    guard::check_source(name, desc, previous.first().map(|s| s.path.as_path()))?;
+    let link_dests: Vec<PathBuf> = previous.iter().map(|s| s.path.join(name)).collect();
+    if link_dests.is_empty() {
+        warning!(
+            "No previous copy of '{}' was found. It will be copied in full.",
+            name
+        );
+    }
+    let issues = match desc.engine {
+        BackupEngine::Rsync => {
+            rsync_named_backup(backup_dir, &link_dests, name, desc, progress, token)?
+        }
+        BackupEngine::Builtin => {
+            if !desc.backup_root.is_dir() {
+                return Err(Box::new(ErrBackupRootNotDir));
+            }
+            info!("Creating '{}' backup.", &name);
+            let issues = engine::create_snapshot(
+                &desc.backup_root,
+                &backup_dir.join(name),
+                link_dests.first().map(PathBuf::as_path),
+                desc,
+                progress,
+                token,
+            )?;
+            write_engine_log(&archive::log_path(backup_dir, name), &issues)?;
+            issues
+        }
+    };
+
+    // Aplica a política do usuário para transferências incompletas
+    if !issues.partial.is_empty() {
+        apply_policy(
+            desc.on_partial_transfer,
+            IssueKind::Partial,
+            name,
+            &issues.partial,
+        )?;
+    }
+    if !issues.vanished.is_empty() {
+        apply_policy(
+            desc.on_vanished_files,
+            IssueKind::Vanished,
+            name,
+            &issues.vanished,
+        )?;
+    }
+    Ok(())
+}
+
+/// Usa o rsync para gerar um backup nomeado na pasta de backups. Devolve os
+/// arquivos afetados caso o rsync termine com uma transferência incompleta.
+fn rsync_named_backup(
+    backup_dir: &Path,
+    link_dests: &[PathBuf],
+    name: &str,
+    desc: &BackupDescription,
+    progress: ProgressHandle,
+    token: &CancelToken,
+) -> BackyResult<TransferIssues> {
     let backup_root_str = gen_backup_root_str(&desc.backup_root)?;
-    let mut latest_link = latest_link.to_owned();
-    latest_link.push(name);
 
     // Cria o comando `rsync` para o backup dos arquivos selecionados
     let mut rsync_command = process::Command::new("rsync");
     rsync_command
-        .current_dir(&backup_dir)
+        .current_dir(backup_dir)
         .arg(&backup_root_str)
         .args(["-az", "--delete"])
-        .arg("--link-dest")
-        .arg(latest_link)
-        .arg(&name);
+        .args(rsync::progress_args());
+    for link_dest in link_dests {
+        rsync_command.arg("--link-dest").arg(link_dest);
+    }
+    rsync_command.arg(name);
 
     if let Some(exclude_files) = &desc.exclude_files {
         let exclude_arg = gen_exclude_arg(exclude_files)?;
         rsync_command.args(exclude_arg);
     }
 
-    // Executa o backupaa
+    // Grava a saída do rsync junto com o snapshot
+    let log_path = archive::log_path(backup_dir, name);
+    let log = create_log(&log_path)?;
+    if let Some(format) = &desc.log_file_format {
+        rsync_command
+            .arg(format!("--log-file={}", log_path.display()))
+            .arg(format!("--log-file-format={}", format));
+    }
+
+    // Executa o backup
     info!("Creating '{}' backup.", &name);
-    let rsync_status = rsync_command.status().unwrap();
-    if !rsync_status.success() {
-        return Err(Box::new(ErrRsyncFail));
+    let command_line = runner::command_line(&rsync_command);
+    let run = match rsync::run(&mut rsync_command, progress, log, token) {
+        Ok(run) => run,
+        Err(err) => return Err(Box::new(ErrRsyncSpawn { command_line, err })),
+    };
+    let mut issues = run.issues;
+    match run.outcome {
+        RsyncOutcome::Success => Ok(TransferIssues::default()),
+        // NOTE: o rsync só lista os arquivos afetados no stderr, então garante
+        // que o tipo de problema seja reportado mesmo sem a lista
+        RsyncOutcome::PartialTransfer if issues.partial.is_empty() => {
+            issues
+                .partial
+                .push(format!("{} (see rsync output)", desc.backup_root.display()));
+            Ok(issues)
+        }
+        RsyncOutcome::VanishedFiles if issues.vanished.is_empty() => {
+            issues
+                .vanished
+                .push(format!("{} (see rsync output)", desc.backup_root.display()));
+            Ok(issues)
+        }
+        RsyncOutcome::PartialTransfer | RsyncOutcome::VanishedFiles => Ok(issues),
+        RsyncOutcome::Cancelled(reason) => Err(reason.into_error(name)),
+        RsyncOutcome::Failure(code) => Err(Box::new(ErrRsyncFail {
+            name: name.to_string(),
+            command_line,
+            code,
+            duration: run.duration,
+            log_path,
+            stderr_tail: run.stderr_tail,
+        })),
     }
+}
 
+/// Cria o arquivo de log de um backup, junto com os diretórios necessários
+// NOTE: o log é esvaziado uma vez e aberto em modo de adição, porque o rsync
+// também escreve nele (com `--log-file`). Sem isso, cada escrita do backy
+// sobrescreveria as linhas do rsync.
+fn create_log(log_path: &Path) -> BackyResult<File> {
+    let open = || -> io::Result<File> {
+        if let Some(parent) = log_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        File::create(log_path)?;
+        OpenOptions::new().append(true).open(log_path)
+    };
+    open().map_err(|err| {
+        Box::new(ErrLogCreate {
+            path: log_path.to_owned(),
+            err,
+        }) as Box<dyn BackyError>
+    })
+}
+
+/// Grava no log do backup os arquivos que o motor embutido não conseguiu copiar
+fn write_engine_log(log_path: &Path, issues: &TransferIssues) -> BackyResult<()> {
+    let mut log = create_log(log_path)?;
+    let lines = issues
+        .partial
+        .iter()
+        .map(|file| format!("failed: {}", file))
+        .chain(
+            issues
+                .vanished
+                .iter()
+                .map(|file| format!("vanished: {}", file)),
+        );
+    for line in lines {
+        writeln!(log, "{}", line).ok();
+    }
     Ok(())
 }
 
+/// Registra o manifesto com os hashes dos arquivos de um backup recém criado.
+/// Arquivos inalterados desde o último snapshot reaproveitam o hash anterior.
+fn record_manifest(backup_dir: &Path, previous: Option<&Snapshot>, name: &str) -> BackyResult<()> {
+    let previous =
+        previous.and_then(|snapshot| Manifest::load(&Manifest::path(&snapshot.path, name)).ok());
+    info!("Recording '{}' manifest.", &name);
+    match Manifest::generate(&backup_dir.join(name), previous.as_ref()) {
+        Ok(manifest) => manifest.save(&Manifest::path(backup_dir, name)),
+        Err(err) => Err(Box::new(ErrManifestFail {
+            name: name.to_string(),
+            err,
+        })),
+    }
+}
+
 /// Gera diretivas --exclude para os arquivos passados pelo usuário
 fn gen_exclude_arg(exclude_files: &[String]) -> BackyResult<Vec<&str>> {
     let mut exclude_arg = Vec::new();
@@ -166,9 +482,121 @@ impl BackyError for ErrLatestUpdate {
 }
 
 /// Erro lançado quando algum problema é encontrado na execução do comando rsync
-struct ErrRsyncFail;
+struct ErrRsyncFail {
+    name: String,
+    command_line: String,
+    code: Option<i32>,
+    duration: Duration,
+    log_path: PathBuf,
+    stderr_tail: Vec<String>,
+}
 impl BackyError for ErrRsyncFail {
     fn get_err_msg(&self) -> String {
-        "rsync failed to create user backup. Error description can be found above.".into()
+        let code = self
+            .code
+            .map(|code| format!(", exit code {}", code))
+            .unwrap_or_default();
+        let mut msg = format!(
+            "rsync failed to create '{}' backup ({}{}) after {:.1}s.\nCommand: {}",
+            self.name,
+            rsync::describe_exit_code(self.code),
+            code,
+            self.duration.as_secs_f64(),
+            self.command_line
+        );
+        if !self.stderr_tail.is_empty() {
+            msg.push_str("\nLast lines of rsync output:");
+            for line in &self.stderr_tail {
+                msg.push_str(&format!("\n  {}", line));
+            }
+        }
+        msg.push_str(&format!("\nFull log: {}", self.log_path.display()));
+        msg
+    }
+}
+
+/// Erro lançado quando não é possível criar o arquivo de log de um backup
+struct ErrLogCreate {
+    path: PathBuf,
+    err: io::Error,
+}
+impl BackyError for ErrLogCreate {
+    fn get_err_msg(&self) -> String {
+        format!(
+            "unable to create log file '{}':\n{}",
+            self.path.display(),
+            self.err
+        )
+    }
+}
+
+/// Erro lançado quando não é possível executar o rsync
+struct ErrRsyncSpawn {
+    command_line: String,
+    err: io::Error,
+}
+impl BackyError for ErrRsyncSpawn {
+    fn get_err_msg(&self) -> String {
+        format!("unable to run `{}`:\n{}", self.command_line, self.err)
+    }
+}
+
+/// Erro lançado quando não é possível calcular o manifesto de um backup
+struct ErrManifestFail {
+    name: String,
+    err: io::Error,
+}
+impl BackyError for ErrManifestFail {
+    fn get_err_msg(&self) -> String {
+        format!(
+            "unable to compute the manifest of '{}' backup:\n{}",
+            self.name, self.err
+        )
+    }
+}
+
+/// Erro lançado quando não é possível marcar um snapshot como parcial
+struct ErrPartialMark {
+    err: io::Error,
+}
+impl BackyError for ErrPartialMark {
+    fn get_err_msg(&self) -> String {
+        format!("unable to record the snapshot status:\n{}", self.err)
+    }
+}
+
+/// Erro lançado quando a thread de um backup entra em pânico
+struct ErrBackupPanicked {
+    name: String,
+}
+impl BackyError for ErrBackupPanicked {
+    fn get_err_msg(&self) -> String {
+        format!(
+            "'{}' backup stopped unexpectedly (internal error)",
+            self.name
+        )
+    }
+}
+
+/// Erro lançado quando o usuário interrompe o programa durante o backup
+struct ErrInterrupted;
+impl BackyError for ErrInterrupted {
+    fn get_err_msg(&self) -> String {
+        "update interrupted. The snapshot was marked as partial and `latest` was kept unchanged."
+            .into()
+    }
+}
+
+/// Erro lançado quando um ou mais backups não puderam ser criados
+struct ErrBackupsFailed {
+    names: Vec<String>,
+}
+impl BackyError for ErrBackupsFailed {
+    fn get_err_msg(&self) -> String {
+        format!(
+            "{} backup(s) failed: {}. The snapshot was marked as partial and `latest` was kept unchanged.",
+            self.names.len(),
+            self.names.join(", ")
+        )
     }
 }
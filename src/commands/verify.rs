@@ -0,0 +1,196 @@
+use super::{BackyCommand, ErrUnexpectedArg};
+
+use crate::{
+    archive::{self, Snapshot},
+    config::Config,
+    error::{BackyError, BackyResult},
+    logging::{info, log, warning},
+    manifest::{self, Manifest},
+};
+
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+};
+
+// #######################
+//   Definições públicas
+// #######################
+/// Confere a integridade dos snapshots comparando o conteúdo atual dos arquivos
+/// com os manifestos gravados durante o `update`.
+pub struct CmdVerify {
+    /// Snapshot que deve ser verificado. Se não for informado, todos os
+    /// snapshots do arquivo são verificados.
+    snapshot: Option<String>,
+}
+
+impl CmdVerify {
+    pub fn from_args(args: &[String]) -> BackyResult<Self> {
+        match args {
+            [] => Ok(Self { snapshot: None }),
+            [snapshot] => Ok(Self {
+                snapshot: Some(snapshot.clone()),
+            }),
+            [_, extra, ..] => Err(Box::new(ErrUnexpectedArg { arg: extra.clone() })),
+        }
+    }
+}
+
+impl BackyCommand for CmdVerify {
+    fn execute(&self, config: Config) -> BackyResult<()> {
+        archive::check_destination(&config)?;
+        let snapshots = match &self.snapshot {
+            Some(name) => vec![archive::find_snapshot(&config.archive_path, name)?],
+            None => archive::list_snapshots(&config.archive_path)?,
+        };
+
+        // NOTE: snapshots compartilham arquivos inalterados por hardlinks, então
+        // cada inode só precisa ter seu hash calculado uma única vez
+        let mut hashes = HashMap::new();
+        let mut problems = 0;
+        let mut verified = 0;
+        for snapshot in &snapshots {
+            if let Some(found) = verify_snapshot(snapshot, &mut hashes)? {
+                problems += found;
+                verified += 1;
+            }
+        }
+
+        if problems > 0 {
+            return Err(Box::new(ErrVerifyFail { problems }));
+        }
+        if verified == 0 {
+            warning!("No snapshot with a manifest was found. Nothing was verified.");
+        } else {
+            info!("All {} verified snapshot(s) are intact.", verified);
+        }
+        Ok(())
+    }
+}
+
+// #######################
+//   Definições privadas
+// #######################
+/// Cache de hashes já calculados, indexados por (dispositivo, inode)
+type HashCache = HashMap<(u64, u64), blake3::Hash>;
+
+/// Verifica todos os backups de um snapshot, devolvendo o número de problemas
+/// encontrados, ou `None` se o snapshot não tem manifesto
+fn verify_snapshot(snapshot: &Snapshot, hashes: &mut HashCache) -> BackyResult<Option<usize>> {
+    let backups = Manifest::recorded_backups(&snapshot.path);
+    if backups.is_empty() {
+        warning!(
+            "Snapshot '{}' has no manifest. Skipping verification.",
+            snapshot.name
+        );
+        return Ok(None);
+    }
+
+    info!("Verifying snapshot '{}'.", snapshot.name);
+    let mut problems = 0;
+    for name in backups {
+        let manifest = Manifest::load(&Manifest::path(&snapshot.path, &name))?;
+        let backup_dir = snapshot.path.join(&name);
+        let report = match verify_backup(&backup_dir, &manifest, hashes) {
+            Ok(report) => report,
+            Err(err) => {
+                return Err(Box::new(ErrVerifyIo {
+                    name: format!("{}/{}", snapshot.name, name),
+                    err,
+                }))
+            }
+        };
+        for (kind, files) in [
+            ("missing", &report.missing),
+            ("modified", &report.modified),
+            ("extra", &report.extra),
+        ] {
+            for file in files {
+                warning!("{}: {}/{}/{}", kind, snapshot.name, name, file);
+            }
+        }
+        problems += report.missing.len() + report.modified.len() + report.extra.len();
+    }
+    Ok(Some(problems))
+}
+
+/// Diferenças encontradas entre um backup e o seu manifesto
+#[derive(Default)]
+struct VerifyReport {
+    missing: Vec<String>,
+    modified: Vec<String>,
+    extra: Vec<String>,
+}
+
+/// Compara o conteúdo de um backup com o seu manifesto
+fn verify_backup(
+    backup_dir: &Path,
+    manifest: &Manifest,
+    hashes: &mut HashCache,
+) -> io::Result<VerifyReport> {
+    let mut report = VerifyReport::default();
+    let on_disk = if backup_dir.is_dir() {
+        manifest::walk_files(backup_dir)?
+    } else {
+        Vec::new()
+    };
+
+    for (rel, meta) in &on_disk {
+        let entry = match manifest.entries.get(rel) {
+            Some(entry) => entry,
+            None => {
+                report.extra.push(rel.display().to_string());
+                continue;
+            }
+        };
+        let key = (meta.dev(), meta.ino());
+        let hash = match hashes.get(&key) {
+            Some(hash) => *hash,
+            None => {
+                let hash = manifest::hash_file(&backup_dir.join(rel))?;
+                hashes.insert(key, hash);
+                hash
+            }
+        };
+        if hash != entry.hash || meta.len() != entry.size {
+            report.modified.push(rel.display().to_string());
+        }
+    }
+
+    let present: HashSet<&PathBuf> = on_disk.iter().map(|(rel, _)| rel).collect();
+    for rel in manifest.entries.keys() {
+        if !present.contains(rel) {
+            report.missing.push(rel.display().to_string());
+        }
+    }
+    Ok(report)
+}
+
+// #######################
+//         Erros
+// #######################
+/// Erro lançado quando a verificação encontra arquivos faltando ou alterados
+struct ErrVerifyFail {
+    problems: usize,
+}
+impl BackyError for ErrVerifyFail {
+    fn get_err_msg(&self) -> String {
+        format!(
+            "verification found {} problem(s). Details can be found above.",
+            self.problems
+        )
+    }
+}
+
+/// Erro lançado quando não é possível ler os arquivos de um backup
+struct ErrVerifyIo {
+    name: String,
+    err: io::Error,
+}
+impl BackyError for ErrVerifyIo {
+    fn get_err_msg(&self) -> String {
+        format!("unable to read backup '{}':\n{}", self.name, self.err)
+    }
+}
@@ -1,7 +1,12 @@
 use crate::error::{BackyError, BackyResult};
-use std::{collections::HashMap, fs, path::PathBuf};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt, fs,
+    path::PathBuf,
+    time::Duration,
+};
 
-use serde::{Deserialize, Serialize};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 
 // #######################
 //   Definições públicas
@@ -11,16 +16,259 @@ use serde::{Deserialize, Serialize};
 pub struct Config {
     /// Local do disco onde devem ser armazenados os backups incrementais.
     pub archive_path: PathBuf,
+    /// Identificação do disco onde fica `archive_path`. Quando configurada, o
+    /// programa se recusa a usar o arquivo se o disco não for o esperado.
+    pub archive_device: Option<ArchiveDevice>,
     /// Número máximo de dias que um backup deve armazenado pelo programa.
-    pub remove_older_than: i64,
-    /// Nome do remote que deve ser usado pelo rclone para sincronizar os arquivos
-    pub rclone_remote: String,
+    /// Ignorado quando a seção `[retention]` está configurada.
+    pub remove_older_than: Option<i64>,
+    /// Regras de retenção no estilo avô-pai-filho usadas pelo `clean`.
+    pub retention: Option<RetentionPolicy>,
+    /// Metas de espaço em disco. O `clean` remove os snapshots mais antigos até
+    /// que elas sejam atingidas.
+    pub capacity: Option<CapacityPolicy>,
+    /// Tempo máximo de execução do `update` como um todo. Backups que não
+    /// terminarem a tempo são interrompidos.
+    pub run_timeout: Option<TimeSpan>,
+    /// Nome do remote que deve ser usado pelo rclone para sincronizar os
+    /// arquivos. Equivale a uma seção `[remotes.default]` com apenas o
+    /// `target`.
+    pub rclone_remote: Option<String>,
+    /// Remotes para onde os snapshots são enviados, cada um com as suas
+    /// regras. As chaves são os nomes usados no `backy remote`.
+    #[serde(default)]
+    pub remotes: BTreeMap<String, RemoteSettings>,
+    /// Formato e nível de compressão dos snapshots enviados ao remote. Por
+    /// padrão, usa gzip.
+    pub compression: Option<CompressionSettings>,
+    /// Criptografia dos arquivos enviados ao remote. Sem ela, o envio só é
+    /// feito se `allow_unencrypted` for verdadeiro.
+    pub encryption: Option<EncryptionSettings>,
+    /// Permite enviar os snapshots ao remote sem criptografia.
+    #[serde(default)]
+    pub allow_unencrypted: bool,
+    /// Forma de envio dos arquivos ao remote. Por padrão, eles são enviados
+    /// direto ao rclone, sem arquivos temporários.
+    pub upload: Option<UploadSettings>,
     /// Descrição dos backups que devem ser feitos automaticamente pelo
     /// programa. As chaves são usadas para nomear os diretórios onde cada
     /// backup será encontrado e devem, portanto, ser únicas.
     pub backups: HashMap<String, BackupDescription>,
 }
 
+impl Config {
+    /// Remotes configurados, incluindo o `rclone_remote` (como "default"). As
+    /// configurações que um remote não define são herdadas das seções
+    /// globais.
+    pub fn remotes(&self) -> BTreeMap<String, RemoteSettings> {
+        let mut remotes = self.remotes.clone();
+        if let Some(target) = &self.rclone_remote {
+            remotes
+                .entry(DEFAULT_REMOTE.to_string())
+                .or_insert_with(|| RemoteSettings {
+                    target: target.clone(),
+                    ..Default::default()
+                });
+        }
+        for remote in remotes.values_mut() {
+            remote.compression = remote.compression.take().or(self.compression.clone());
+            remote.encryption = remote.encryption.take().or(self.encryption.clone());
+            remote.upload = remote.upload.take().or(self.upload.clone());
+        }
+        remotes
+    }
+}
+
+/// Nome do remote criado a partir do `rclone_remote`
+pub const DEFAULT_REMOTE: &str = "default";
+
+/// Um destino do rclone para onde os snapshots são enviados
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct RemoteSettings {
+    /// Destino no rclone (ex: "gdrive:" ou "gdrive:backups/notebook").
+    pub target: String,
+    /// Backups enviados a esse remote. Por padrão, todos.
+    pub backups: Option<Vec<String>>,
+    /// Compressão dos arquivos. Por padrão, usa a seção `[compression]`.
+    pub compression: Option<CompressionSettings>,
+    /// Criptografia dos arquivos. Por padrão, usa a seção `[encryption]`.
+    pub encryption: Option<EncryptionSettings>,
+    /// Forma de envio dos arquivos. Por padrão, usa a seção `[upload]`.
+    pub upload: Option<UploadSettings>,
+    /// Intervalo mínimo entre dois envios ao remote. Remotes que receberam um
+    /// snapshot há menos tempo são ignorados, a não ser que sejam escolhidos
+    /// pelo nome.
+    pub frequency: Option<TimeSpan>,
+    /// Regras de retenção dos arquivos no remote, usadas pelo `remote prune`.
+    /// São independentes das regras do arquivo local.
+    pub retention: Option<RemoteRetention>,
+}
+
+/// Regras de retenção dos arquivos enviados a um remote. Um arquivo é mantido
+/// se qualquer regra o mantiver.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct RemoteRetention {
+    /// Mantém os arquivos com menos de N dias.
+    pub remove_older_than: Option<i64>,
+    /// Regras no estilo avô-pai-filho, como as do arquivo local.
+    #[serde(flatten)]
+    pub rules: RetentionPolicy,
+}
+
+/// Regras de retenção que decidem quais snapshots são mantidos. Cada regra
+/// mantém os N snapshots mais recentes do seu período (dia, semana, mês ou
+/// ano), e um snapshot é mantido se qualquer regra o mantiver.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct RetentionPolicy {
+    /// Mantém os N snapshots mais recentes.
+    pub keep_last: Option<usize>,
+    /// Mantém o snapshot mais recente de cada um dos últimos N dias.
+    pub keep_daily: Option<usize>,
+    /// Mantém o snapshot mais recente de cada uma das últimas N semanas.
+    pub keep_weekly: Option<usize>,
+    /// Mantém o snapshot mais recente de cada um dos últimos N meses.
+    pub keep_monthly: Option<usize>,
+    /// Mantém o snapshot mais recente de cada um dos últimos N anos.
+    pub keep_yearly: Option<usize>,
+}
+
+/// Metas de espaço em disco para o arquivo de backups
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct CapacityPolicy {
+    /// Espaço livre mínimo no disco do arquivo (ex: "50G" ou "10%").
+    pub min_free_space: Option<SpaceAmount>,
+    /// Tamanho máximo ocupado pelos snapshots.
+    pub max_archive_size: Option<ByteSize>,
+    /// Número mínimo de snapshots mantidos, mesmo que as metas não sejam
+    /// atingidas. Nunca é menor que 1.
+    #[serde(default = "default_min_keep")]
+    pub min_keep: usize,
+    /// Remove snapshots antes do `update` quando o espaço livre estiver abaixo
+    /// de `min_free_space`.
+    #[serde(default)]
+    pub prune_before_update: bool,
+}
+
+/// Uma quantidade de espaço em disco, absoluta ou relativa ao tamanho do disco
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpaceAmount {
+    Bytes(ByteSize),
+    /// Porcentagem do tamanho total do disco
+    Percent(u8),
+}
+
+impl SpaceAmount {
+    /// Interpreta uma string como uma porcentagem ("10%") ou um tamanho
+    pub fn parse(raw: &str) -> Option<Self> {
+        match raw.trim().strip_suffix('%') {
+            Some(percent) => match percent.trim().parse() {
+                Ok(percent) if percent <= 100 => Some(SpaceAmount::Percent(percent)),
+                _ => None,
+            },
+            None => ByteSize::parse(raw).map(SpaceAmount::Bytes),
+        }
+    }
+
+    /// Converte a quantidade em bytes, para um disco com `total` bytes
+    pub fn resolve(&self, total: u64) -> ByteSize {
+        match self {
+            SpaceAmount::Bytes(bytes) => *bytes,
+            SpaceAmount::Percent(percent) => ByteSize(total / 100 * *percent as u64),
+        }
+    }
+}
+
+impl fmt::Display for SpaceAmount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SpaceAmount::Bytes(bytes) => write!(f, "{}", bytes),
+            SpaceAmount::Percent(percent) => write!(f, "{}%", percent),
+        }
+    }
+}
+
+/// Compressão dos arquivos enviados ao remote
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct CompressionSettings {
+    /// Algoritmo de compressão.
+    #[serde(default)]
+    pub format: CompressionFormat,
+    /// Nível de compressão (1 a 9 no gzip e no xz, 1 a 22 no zstd). Sem ele,
+    /// usa o padrão de cada algoritmo.
+    pub level: Option<u32>,
+    /// Número de threads usadas pelo zstd. Por padrão, usa todos os núcleos.
+    pub threads: Option<u32>,
+}
+
+/// Algoritmos de compressão disponíveis para os arquivos enviados ao remote
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CompressionFormat {
+    #[default]
+    Gzip,
+    Zstd,
+    Xz,
+}
+
+/// Criptografia dos arquivos enviados ao remote, feita com o age. Deve usar
+/// chaves públicas (`recipients`) ou uma senha, lida de um arquivo ou da saída
+/// de um comando.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct EncryptionSettings {
+    /// Chaves públicas do age (`age1...`) que podem abrir os arquivos.
+    #[serde(default)]
+    pub recipients: Vec<String>,
+    /// Arquivo com as chaves privadas (`AGE-SECRET-KEY-...`) usadas para abrir
+    /// os arquivos no `decrypt`.
+    pub identity_file: Option<PathBuf>,
+    /// Arquivo cuja primeira linha é a senha.
+    pub passphrase_file: Option<PathBuf>,
+    /// Comando que escreve a senha na saída padrão (ex: "pass show backy").
+    pub passphrase_command: Option<String>,
+}
+
+/// Forma de envio dos arquivos ao remote
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct UploadSettings {
+    /// Envia os arquivos enquanto são gerados ("stream") ou os grava antes em
+    /// `staging_dir` ("staged").
+    #[serde(default)]
+    pub mode: UploadMode,
+    /// Diretório onde os arquivos são gravados antes do envio no modo
+    /// "staged" e onde ficam os volumes de um envio interrompido. Por padrão,
+    /// usa o diretório temporário do sistema.
+    pub staging_dir: Option<PathBuf>,
+    /// Divide os arquivos em volumes de até esse tamanho (`<arquivo>.001`,
+    /// `<arquivo>.002`, ...), enviados um por vez pelo diretório temporário.
+    /// Se um volume não puder ser enviado, os demais ficam no diretório até a
+    /// próxima execução.
+    pub volume_size: Option<ByteSize>,
+}
+
+/// Modos de envio dos arquivos ao remote
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum UploadMode {
+    /// Envia o arquivo ao `rclone rcat` enquanto ele é gerado
+    #[default]
+    Stream,
+    /// Grava o arquivo no diretório temporário e o envia com `rclone copy`
+    Staged,
+}
+
+/// Formas de identificar o sistema de arquivos onde o arquivo de backups deve
+/// estar. Todas as identificações configuradas precisam ser satisfeitas.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct ArchiveDevice {
+    /// UUID do sistema de arquivos (como em `/dev/disk/by-uuid`).
+    pub uuid: Option<String>,
+    /// Rótulo do sistema de arquivos (como em `/dev/disk/by-label`).
+    pub label: Option<String>,
+    /// Identificador que deve constar no arquivo `.backy-archive` na raíz de
+    /// `archive_path`.
+    pub marker_id: Option<String>,
+}
+
 /// Descreve uma raíz de backup
 #[derive(Serialize, Deserialize, Debug)]
 pub struct BackupDescription {
@@ -28,6 +276,228 @@ pub struct BackupDescription {
     pub backup_root: PathBuf,
     /// Lista dos arquivos/diretórios que devem ser ignorados pelo backup.
     pub exclude_files: Option<Vec<String>>,
+    /// Motor usado para criar o snapshot. Por padrão, usa o rsync.
+    #[serde(default)]
+    pub engine: BackupEngine,
+    /// Compara o conteúdo dos arquivos (e não apenas tamanho e data de
+    /// modificação) para decidir se eles mudaram. Usado pelo motor embutido.
+    #[serde(default)]
+    pub checksum: bool,
+    /// O que fazer quando alguns arquivos não puderem ser lidos (código 23 do
+    /// rsync).
+    #[serde(default)]
+    pub on_partial_transfer: PolicyAction,
+    /// O que fazer quando alguns arquivos desaparecerem durante o backup
+    /// (código 24 do rsync).
+    #[serde(default)]
+    pub on_vanished_files: PolicyAction,
+    /// Verificações feitas na origem antes de criar o snapshot, que impedem
+    /// que uma origem desmontada ou apagada gere um backup vazio.
+    pub guard: Option<SourceGuard>,
+    /// Formato da lista de mudanças gravada pelo rsync no log do backup (como
+    /// em `--log-file-format`, ex: "%i %n%L"). Sem ele, o log contém apenas a
+    /// saída do rsync.
+    pub log_file_format: Option<String>,
+    /// Tempo máximo que o backup pode levar. Ao expirar, o backup é
+    /// interrompido e os demais continuam.
+    pub timeout: Option<TimeSpan>,
+    /// Número máximo de snapshots anteriores usados como base para os arquivos
+    /// inalterados (um `--link-dest` para cada, até 20). O motor embutido usa
+    /// apenas o mais recente.
+    #[serde(default = "default_link_dest_count")]
+    pub link_dest_count: usize,
+}
+
+/// Condições que a origem de um backup precisa satisfazer para que o snapshot
+/// seja criado
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct SourceGuard {
+    /// Exige que `backup_root` seja um ponto de montagem.
+    #[serde(default)]
+    pub require_mountpoint: bool,
+    /// Arquivo (relativo a `backup_root`) que precisa existir na origem.
+    pub marker_file: Option<PathBuf>,
+    /// Número mínimo de arquivos na origem.
+    pub min_files: Option<u64>,
+    /// Tamanho mínimo da origem.
+    pub min_size: Option<ByteSize>,
+    /// Porcentagem máxima de arquivos que podem desaparecer em relação ao
+    /// snapshot anterior.
+    #[serde(default, deserialize_with = "deserialize_percent")]
+    pub max_shrink: Option<u8>,
+}
+
+/// Uma quantidade de bytes, que pode ser escrita na configuração como um
+/// inteiro ou como uma string com unidade (ex: "512M", "10G", "1.5TiB").
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ByteSize(pub u64);
+
+impl ByteSize {
+    /// Interpreta uma string com um tamanho e uma unidade opcional. As unidades
+    /// são sempre múltiplos de 1024.
+    pub fn parse(raw: &str) -> Option<Self> {
+        let raw = raw.trim();
+        let split = raw
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(raw.len());
+        let (number, unit) = raw.split_at(split);
+        let number: f64 = number.parse().ok()?;
+        let exponent = match unit.trim().to_ascii_uppercase().as_str() {
+            "" | "B" => 0,
+            "K" | "KB" | "KIB" => 1,
+            "M" | "MB" | "MIB" => 2,
+            "G" | "GB" | "GIB" => 3,
+            "T" | "TB" | "TIB" => 4,
+            _ => return None,
+        };
+        Some(ByteSize((number * 1024f64.powi(exponent)) as u64))
+    }
+}
+
+impl fmt::Display for ByteSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+        let mut value = self.0 as f64;
+        let mut unit = 0;
+        while value >= 1024.0 && unit < UNITS.len() - 1 {
+            value /= 1024.0;
+            unit += 1;
+        }
+        if unit == 0 {
+            write!(f, "{} {}", self.0, UNITS[0])
+        } else {
+            write!(f, "{:.1} {}", value, UNITS[unit])
+        }
+    }
+}
+
+/// Um intervalo de tempo, que pode ser escrito na configuração como um inteiro
+/// (em segundos) ou como uma string com unidade (ex: "90s", "30m", "2h", "1d").
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TimeSpan(pub Duration);
+
+impl TimeSpan {
+    /// Interpreta uma string com um número e uma unidade opcional (s, m, h, d
+    /// ou w). Sem unidade, o número é interpretado como segundos.
+    pub fn parse(raw: &str) -> Option<Self> {
+        let raw = raw.trim();
+        let split = raw.find(|c: char| !c.is_ascii_digit()).unwrap_or(raw.len());
+        let (number, unit) = raw.split_at(split);
+        let number: u64 = number.parse().ok()?;
+        let multiplier = match unit.trim() {
+            "" | "s" => 1,
+            "m" => 60,
+            "h" => 60 * 60,
+            "d" => 24 * 60 * 60,
+            "w" => 7 * 24 * 60 * 60,
+            _ => return None,
+        };
+        Some(TimeSpan(Duration::from_secs(
+            number.checked_mul(multiplier)?,
+        )))
+    }
+}
+
+impl fmt::Display for TimeSpan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let secs = self.0.as_secs();
+        match secs {
+            s if s % 86400 == 0 && s > 0 => write!(f, "{}d", s / 86400),
+            s if s % 3600 == 0 && s > 0 => write!(f, "{}h", s / 3600),
+            s if s % 60 == 0 && s > 0 => write!(f, "{}m", s / 60),
+            s => write!(f, "{}s", s),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for TimeSpan {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Seconds(u64),
+            Text(String),
+        }
+        match Raw::deserialize(deserializer)? {
+            Raw::Seconds(secs) => Ok(TimeSpan(Duration::from_secs(secs))),
+            Raw::Text(text) => TimeSpan::parse(&text)
+                .ok_or_else(|| de::Error::custom(format!("invalid duration '{}'", text))),
+        }
+    }
+}
+
+impl Serialize for TimeSpan {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(self.0.as_secs())
+    }
+}
+
+impl<'de> Deserialize<'de> for ByteSize {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Bytes(u64),
+            Text(String),
+        }
+        match Raw::deserialize(deserializer)? {
+            Raw::Bytes(bytes) => Ok(ByteSize(bytes)),
+            Raw::Text(text) => ByteSize::parse(&text)
+                .ok_or_else(|| de::Error::custom(format!("invalid size '{}'", text))),
+        }
+    }
+}
+
+impl Serialize for ByteSize {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for SpaceAmount {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Bytes(u64),
+            Text(String),
+        }
+        match Raw::deserialize(deserializer)? {
+            Raw::Bytes(bytes) => Ok(SpaceAmount::Bytes(ByteSize(bytes))),
+            Raw::Text(text) => SpaceAmount::parse(&text)
+                .ok_or_else(|| de::Error::custom(format!("invalid space amount '{}'", text))),
+        }
+    }
+}
+
+impl Serialize for SpaceAmount {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+/// Ação tomada quando um backup termina com uma transferência incompleta
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PolicyAction {
+    /// Interrompe o backup com um erro
+    #[default]
+    Error,
+    /// Mostra um aviso com os arquivos afetados, mas mantém o backup
+    Warn,
+    /// Mantém o backup silenciosamente
+    Ignore,
+}
+
+/// Motores disponíveis para a criação dos snapshots incrementais
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BackupEngine {
+    /// Usa o programa externo `rsync`
+    #[default]
+    Rsync,
+    /// Usa a implementação embutida no backy, sem dependências externas
+    Builtin,
 }
 
 /// Carrega o arquivo de configuração do usuário e devolve uma struct com os
@@ -46,6 +516,25 @@ pub fn load() -> BackyResult<Config> {
 // #######################
 //   Definições públicas
 // #######################
+fn default_min_keep() -> usize {
+    1
+}
+
+fn default_link_dest_count() -> usize {
+    1
+}
+
+/// Lê uma porcentagem opcional, recusando valores acima de 100
+fn deserialize_percent<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u8>, D::Error> {
+    match Option::<u8>::deserialize(deserializer)? {
+        Some(percent) if percent > 100 => Err(de::Error::custom(format!(
+            "invalid percentage {} (must be between 0 and 100)",
+            percent
+        ))),
+        percent => Ok(percent),
+    }
+}
+
 /// Lê o arquivo de configuração do usuário e o devolve como uma string
 fn read_config() -> BackyResult<String> {
     // Encontra o path para o arquivo de configuração
@@ -90,3 +579,66 @@ impl BackyError for ErrBadConfigFormat {
         format!("unable to parse config:\n{}", self.err)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_byte_sizes() {
+        assert_eq!(ByteSize::parse("512"), Some(ByteSize(512)));
+        assert_eq!(ByteSize::parse("100B"), Some(ByteSize(100)));
+        assert_eq!(ByteSize::parse("4k"), Some(ByteSize(4 * 1024)));
+        assert_eq!(ByteSize::parse("512M"), Some(ByteSize(512 << 20)));
+        assert_eq!(ByteSize::parse(" 10 GB "), Some(ByteSize(10 << 30)));
+        assert_eq!(ByteSize::parse("1.5TiB"), Some(ByteSize(3 << 39)));
+        for invalid in ["", "G", "10X", "1.2.3M", "-5M"] {
+            assert_eq!(ByteSize::parse(invalid), None, "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn deserializes_byte_sizes() {
+        let guard: SourceGuard = toml::from_str("min_size = \"1K\"").unwrap();
+        assert_eq!(guard.min_size, Some(ByteSize(1024)));
+        let guard: SourceGuard = toml::from_str("min_size = 2048").unwrap();
+        assert_eq!(guard.min_size, Some(ByteSize(2048)));
+        assert!(toml::from_str::<SourceGuard>("min_size = \"lots\"").is_err());
+    }
+
+    #[test]
+    fn parses_time_spans() {
+        let secs = |secs| Some(TimeSpan(Duration::from_secs(secs)));
+        assert_eq!(TimeSpan::parse("90"), secs(90));
+        assert_eq!(TimeSpan::parse("90s"), secs(90));
+        assert_eq!(TimeSpan::parse("30m"), secs(30 * 60));
+        assert_eq!(TimeSpan::parse(" 2 h "), secs(2 * 3600));
+        assert_eq!(TimeSpan::parse("1d"), secs(86400));
+        assert_eq!(TimeSpan::parse("2w"), secs(14 * 86400));
+        for invalid in ["", "h", "1.5h", "10x", "-1m", "3M", "9999999999999999999w"] {
+            assert_eq!(TimeSpan::parse(invalid), None, "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn time_spans_display_in_the_largest_exact_unit() {
+        for (raw, shown) in [
+            ("90s", "90s"),
+            ("120", "2m"),
+            ("3h", "3h"),
+            ("2w", "14d"),
+            ("0", "0s"),
+        ] {
+            assert_eq!(TimeSpan::parse(raw).unwrap().to_string(), shown);
+        }
+    }
+
+    #[test]
+    fn rejects_shrink_above_100_percent() {
+        let guard: SourceGuard = toml::from_str("max_shrink = 100").unwrap();
+        assert_eq!(guard.max_shrink, Some(100));
+        let guard: SourceGuard = toml::from_str("").unwrap();
+        assert_eq!(guard.max_shrink, None);
+        assert!(toml::from_str::<SourceGuard>("max_shrink = 101").is_err());
+    }
+}
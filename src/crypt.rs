@@ -0,0 +1,462 @@
+use crate::{
+    config::EncryptionSettings,
+    error::{BackyError, BackyResult},
+    runner::Process,
+};
+
+use age::{
+    secrecy::SecretString, stream::StreamWriter, x25519, DecryptError, Decryptor, Encryptor,
+    Identity, Recipient,
+};
+use std::{
+    fs,
+    io::{self, BufRead, Read, Write},
+    iter,
+    path::{Path, PathBuf},
+};
+
+// #######################
+//   Definições públicas
+// #######################
+/// Extensão acrescentada aos arquivos criptografados
+pub const EXTENSION: &str = "age";
+
+/// Chave usada para criptografar os arquivos enviados ao remote
+pub enum Key {
+    Recipients(Vec<x25519::Recipient>),
+    Passphrase(SecretString),
+}
+
+impl Key {
+    /// Monta a chave configurada. Exatamente uma forma de chave deve estar
+    /// configurada.
+    pub fn load(settings: &EncryptionSettings) -> BackyResult<Self> {
+        let configured = [
+            !settings.recipients.is_empty(),
+            settings.passphrase_file.is_some(),
+            settings.passphrase_command.is_some(),
+        ];
+        if configured.iter().filter(|&&c| c).count() != 1 {
+            return Err(Box::new(ErrBadEncryptionConfig));
+        }
+        if settings.recipients.is_empty() {
+            return Ok(Key::Passphrase(passphrase(settings)?));
+        }
+        let mut recipients = Vec::new();
+        for raw in &settings.recipients {
+            let recipient = raw
+                .trim()
+                .parse()
+                .map_err(|reason: &str| bad_key(raw, reason.to_string()))?;
+            recipients.push(recipient);
+        }
+        Ok(Key::Recipients(recipients))
+    }
+}
+
+/// Começa a escrita de um arquivo criptografado em `out`. O arquivo segue o
+/// formato do age, e pode ser aberto também com `age -d`.
+///
+/// O `finish` do escritor devolvido precisa ser chamado ao final, ou o arquivo
+/// fica incompleto.
+pub fn encrypt<W: Write>(key: &Key, out: W) -> BackyResult<StreamWriter<W>> {
+    let encryptor = match key {
+        Key::Recipients(recipients) => {
+            Encryptor::with_recipients(recipients.iter().map(|r| r as &dyn Recipient))
+                .map_err(|err| fail_encrypt(err.to_string()))?
+        }
+        Key::Passphrase(passphrase) => Encryptor::with_user_passphrase(passphrase.clone()),
+    };
+    encryptor
+        .wrap_output(out)
+        .map_err(|err| fail_encrypt(err.to_string()))
+}
+
+/// Abre um arquivo criado por [`encrypt`], escrevendo o conteúdo original em
+/// `out`. Arquivos criptografados com chaves públicas são abertos com as chaves
+/// privadas de `identity_file` (ou de `identity_file` na configuração), e os
+/// criptografados com senha, com a senha configurada.
+///
+/// Arquivos criados por versões anteriores do backy, que têm um cabeçalho em
+/// texto antes do conteúdo do age, também são aceitos.
+pub fn decrypt<R: BufRead, W: Write>(
+    settings: Option<&EncryptionSettings>,
+    identity_file: Option<&Path>,
+    mut input: R,
+    out: &mut W,
+) -> BackyResult<u64> {
+    let legacy = match input.fill_buf() {
+        Ok(buf) => buf.starts_with(HEADER_MAGIC.as_bytes()),
+        Err(err) => return Err(fail_decrypt(err.to_string())),
+    };
+    let keys = match legacy {
+        true => LegacyHeader::read(&mut input)?.keys,
+        false => Vec::new(),
+    };
+    let decryptor = Decryptor::new_buffered(input).map_err(|err| fail_decrypt(err.to_string()))?;
+    let scheme = match decryptor.is_scrypt() {
+        true => Scheme::Passphrase,
+        false => Scheme::Recipients,
+    };
+    let mut reader = match scheme {
+        Scheme::Passphrase => {
+            let settings = settings.ok_or_else(|| no_key(scheme))?;
+            let identity = age::scrypt::Identity::new(passphrase(settings)?);
+            decryptor.decrypt(iter::once(&identity as &dyn Identity))
+        }
+        Scheme::Recipients => {
+            let path = identity_file
+                .map(Path::to_path_buf)
+                .or_else(|| settings.and_then(|s| s.identity_file.clone()))
+                .ok_or_else(|| no_key(scheme))?;
+            let identities = read_identities(&path)?;
+            match decryptor.decrypt(identities.iter().map(|i| i as &dyn Identity)) {
+                Err(DecryptError::NoMatchingKeys) => {
+                    return Err(Box::new(ErrWrongKey { path, keys }))
+                }
+                result => result,
+            }
+        }
+    }
+    .map_err(|err| fail_decrypt(err.to_string()))?;
+    io::copy(&mut reader, out).map_err(|err| fail_decrypt(err.to_string()))
+}
+
+// #######################
+//   Definições privadas
+// #######################
+/// Primeira linha do cabeçalho em texto que as versões anteriores do backy
+/// gravavam antes do conteúdo do age
+const HEADER_MAGIC: &str = "backy-encrypted v1";
+
+/// Esquemas de criptografia suportados
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Scheme {
+    /// Chaves públicas X25519 do age
+    Recipients,
+    /// Senha, derivada com scrypt pelo age
+    Passphrase,
+}
+
+/// Cabeçalho em texto dos arquivos criptografados por versões anteriores do
+/// backy:
+///
+/// ```text
+/// backy-encrypted v1
+/// scheme: age-x25519
+/// key: 3f1c9a0e2b7d4c58
+///
+/// ```
+///
+/// Cada linha `key` é a impressão digital de uma das chaves públicas. Como o
+/// esquema também está no cabeçalho do age, apenas as impressões digitais são
+/// usadas, para ajudar a encontrar a chave certa.
+// NOTE: o cabeçalho impedia o `age -d` de abrir os arquivos, então os arquivos
+// novos são gravados sem ele
+struct LegacyHeader {
+    keys: Vec<String>,
+}
+
+impl LegacyHeader {
+    /// Lê o cabeçalho do início de `input`, deixando-o no começo do conteúdo
+    /// criptografado
+    fn read<R: BufRead>(input: &mut R) -> BackyResult<Self> {
+        let mut lines = Vec::new();
+        loop {
+            let mut line = String::new();
+            // NOTE: os limites evitam ler um arquivo inteiro que não seja do
+            // backy como se fosse o cabeçalho
+            let read =
+                Read::take(&mut *input, 1024)
+                    .read_line(&mut line)
+                    .map_err(|err| match err.kind() {
+                        io::ErrorKind::InvalidData => Box::new(ErrBadHeader),
+                        _ => fail_decrypt(err.to_string()),
+                    })?;
+            if read == 0 || !line.ends_with('\n') || lines.len() > 64 {
+                return Err(Box::new(ErrBadHeader));
+            }
+            if line == "\n" {
+                break;
+            }
+            lines.push(line.trim_end().to_string());
+        }
+
+        if lines.first().map(String::as_str) != Some(HEADER_MAGIC) {
+            return Err(Box::new(ErrBadHeader));
+        }
+        let keys = lines[1..]
+            .iter()
+            .filter_map(|line| Some(line.strip_prefix("key: ")?.to_string()))
+            .collect();
+        Ok(LegacyHeader { keys })
+    }
+}
+
+/// Lê a senha configurada, de um arquivo ou da saída de um comando. Apenas a
+/// primeira linha é usada.
+fn passphrase(settings: &EncryptionSettings) -> BackyResult<SecretString> {
+    let raw = if let Some(path) = &settings.passphrase_file {
+        fs::read_to_string(path).map_err(|err| {
+            fail_passphrase(format!("unable to read '{}': {}", path.display(), err))
+        })?
+    } else if let Some(command) = &settings.passphrase_command {
+        let output = Process::new("sh")
+            .arg("-c")
+            .arg(command)
+            .interactive()
+            .run()
+            .map_err(|err| fail_passphrase(err.get_err_msg()))?;
+        String::from_utf8(output.stdout).map_err(|_| {
+            fail_passphrase(format!("the output of `{}` is not valid UTF-8", command))
+        })?
+    } else {
+        return Err(no_key(Scheme::Passphrase));
+    };
+
+    let passphrase = raw.lines().next().unwrap_or_default();
+    if passphrase.is_empty() {
+        return Err(fail_passphrase("the passphrase is empty".into()));
+    }
+    Ok(SecretString::from(passphrase.to_string()))
+}
+
+/// Lê as chaves privadas de um arquivo de identidades do age, ignorando linhas
+/// vazias e comentários
+fn read_identities(path: &Path) -> BackyResult<Vec<x25519::Identity>> {
+    let key = path.display().to_string();
+    let content = fs::read_to_string(path).map_err(|err| bad_key(&key, err.to_string()))?;
+    let mut identities = Vec::new();
+    for line in content.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let identity = line
+            .parse()
+            .map_err(|reason: &str| bad_key(&key, reason.to_string()))?;
+        identities.push(identity);
+    }
+    Ok(identities)
+}
+
+fn no_key(scheme: Scheme) -> Box<dyn BackyError> {
+    Box::new(ErrNoKey { scheme })
+}
+
+fn bad_key(key: &str, reason: String) -> Box<dyn BackyError> {
+    Box::new(ErrBadKey {
+        key: key.to_string(),
+        reason,
+    })
+}
+
+fn fail_passphrase(reason: String) -> Box<dyn BackyError> {
+    Box::new(ErrPassphrase { reason })
+}
+
+fn fail_encrypt(reason: String) -> Box<dyn BackyError> {
+    Box::new(ErrEncrypt { reason })
+}
+
+fn fail_decrypt(reason: String) -> Box<dyn BackyError> {
+    Box::new(ErrDecrypt { reason })
+}
+
+// #######################
+//         Erros
+// #######################
+/// Erro lançado quando a seção `[encryption]` não tem exatamente uma forma de
+/// chave
+struct ErrBadEncryptionConfig;
+impl BackyError for ErrBadEncryptionConfig {
+    fn get_err_msg(&self) -> String {
+        "the [encryption] section must set exactly one of recipients, passphrase_file or passphrase_command".into()
+    }
+}
+
+/// Erro lançado quando uma chave pública ou privada não pode ser lida
+struct ErrBadKey {
+    key: String,
+    reason: String,
+}
+impl BackyError for ErrBadKey {
+    fn get_err_msg(&self) -> String {
+        format!("invalid age key '{}': {}", self.key, self.reason)
+    }
+}
+
+/// Erro lançado quando não é possível obter a senha configurada
+struct ErrPassphrase {
+    reason: String,
+}
+impl BackyError for ErrPassphrase {
+    fn get_err_msg(&self) -> String {
+        format!("unable to get the encryption passphrase: {}", self.reason)
+    }
+}
+
+/// Erro lançado quando não há uma chave para abrir o arquivo
+struct ErrNoKey {
+    scheme: Scheme,
+}
+impl BackyError for ErrNoKey {
+    fn get_err_msg(&self) -> String {
+        match self.scheme {
+            Scheme::Recipients => "the file is encrypted with public keys. Pass an identity file with --identity or set identity_file in the [encryption] section".into(),
+            Scheme::Passphrase => "the file is encrypted with a passphrase. Set passphrase_file or passphrase_command in the [encryption] section".into(),
+        }
+    }
+}
+
+/// Erro lançado quando nenhuma das chaves privadas corresponde às chaves
+/// públicas usadas no arquivo
+struct ErrWrongKey {
+    path: PathBuf,
+    keys: Vec<String>,
+}
+impl BackyError for ErrWrongKey {
+    fn get_err_msg(&self) -> String {
+        let message = format!(
+            "no identity in '{}' matches the keys of the file",
+            self.path.display()
+        );
+        match self.keys.is_empty() {
+            true => message,
+            false => format!("{} (fingerprints: {})", message, self.keys.join(", ")),
+        }
+    }
+}
+
+/// Erro lançado quando o cabeçalho de um arquivo de uma versão anterior do
+/// backy está corrompido
+struct ErrBadHeader;
+impl BackyError for ErrBadHeader {
+    fn get_err_msg(&self) -> String {
+        "the file has a damaged backy header".into()
+    }
+}
+
+/// Erro lançado quando não é possível criptografar o arquivo
+struct ErrEncrypt {
+    reason: String,
+}
+impl BackyError for ErrEncrypt {
+    fn get_err_msg(&self) -> String {
+        format!("unable to encrypt the archive:\n{}", self.reason)
+    }
+}
+
+/// Erro lançado quando não é possível abrir o arquivo criptografado
+struct ErrDecrypt {
+    reason: String,
+}
+impl BackyError for ErrDecrypt {
+    fn get_err_msg(&self) -> String {
+        format!("unable to decrypt the archive:\n{}", self.reason)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use age::secrecy::ExposeSecret;
+
+    const CONTENT: &[u8] = b"snapshot contents";
+
+    fn encrypt_bytes(key: &Key, content: &[u8]) -> Vec<u8> {
+        let mut writer =
+            encrypt(key, Vec::new()).unwrap_or_else(|err| panic!("{}", err.get_err_msg()));
+        writer.write_all(content).unwrap();
+        writer.finish().unwrap()
+    }
+
+    fn decrypt_bytes(
+        settings: Option<&EncryptionSettings>,
+        identity_file: Option<&Path>,
+        encrypted: &[u8],
+    ) -> BackyResult<Vec<u8>> {
+        let mut out = Vec::new();
+        decrypt(settings, identity_file, encrypted, &mut out)?;
+        Ok(out)
+    }
+
+    /// Grava uma chave privada nova em `dir`, devolvendo o arquivo e a chave
+    /// pública correspondente
+    fn identity_file(dir: &Path, name: &str) -> (PathBuf, x25519::Recipient) {
+        let identity = x25519::Identity::generate();
+        let path = dir.join(name);
+        let content = format!("# test key\n{}\n", identity.to_string().expose_secret());
+        fs::write(&path, content).unwrap();
+        (path, identity.to_public())
+    }
+
+    #[test]
+    fn recipients_round_trip_as_plain_age() {
+        let dir = tempfile::tempdir().unwrap();
+        let (path, recipient) = identity_file(dir.path(), "key.txt");
+        let encrypted = encrypt_bytes(&Key::Recipients(vec![recipient]), CONTENT);
+        // NOTE: sem cabeçalho próprio, o arquivo pode ser aberto com `age -d`
+        assert!(encrypted.starts_with(b"age-encryption.org/v1\n"));
+
+        let decrypted = decrypt_bytes(None, Some(&path), &encrypted)
+            .unwrap_or_else(|err| panic!("{}", err.get_err_msg()));
+        assert_eq!(decrypted, CONTENT);
+    }
+
+    #[test]
+    fn passphrase_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let passphrase_file = dir.path().join("passphrase");
+        fs::write(&passphrase_file, "correct horse\nignored line\n").unwrap();
+        let settings = EncryptionSettings {
+            passphrase_file: Some(passphrase_file),
+            ..EncryptionSettings::default()
+        };
+        let key = Key::load(&settings).unwrap_or_else(|err| panic!("{}", err.get_err_msg()));
+        let encrypted = encrypt_bytes(&key, CONTENT);
+
+        let decrypted = decrypt_bytes(Some(&settings), None, &encrypted)
+            .unwrap_or_else(|err| panic!("{}", err.get_err_msg()));
+        assert_eq!(decrypted, CONTENT);
+        // Sem a senha configurada, o erro explica o que falta
+        let err = decrypt_bytes(None, None, &encrypted).err().unwrap();
+        assert!(err.get_err_msg().contains("passphrase"));
+    }
+
+    #[test]
+    fn wrong_identity_is_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        let (_, recipient) = identity_file(dir.path(), "right.txt");
+        let (wrong, _) = identity_file(dir.path(), "wrong.txt");
+        let encrypted = encrypt_bytes(&Key::Recipients(vec![recipient]), CONTENT);
+
+        let err = decrypt_bytes(None, Some(&wrong), &encrypted).err().unwrap();
+        assert!(err.get_err_msg().starts_with("no identity in"));
+    }
+
+    #[test]
+    fn legacy_header_is_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        let (path, recipient) = identity_file(dir.path(), "key.txt");
+        let mut encrypted = format!(
+            "{}\nscheme: age-x25519\nkey: 3f1c9a0e2b7d4c58\n\n",
+            HEADER_MAGIC
+        )
+        .into_bytes();
+        encrypted.extend(encrypt_bytes(&Key::Recipients(vec![recipient]), CONTENT));
+
+        let decrypted = decrypt_bytes(None, Some(&path), &encrypted)
+            .unwrap_or_else(|err| panic!("{}", err.get_err_msg()));
+        assert_eq!(decrypted, CONTENT);
+        let (wrong, _) = identity_file(dir.path(), "wrong.txt");
+        let err = decrypt_bytes(None, Some(&wrong), &encrypted).err().unwrap();
+        assert!(err.get_err_msg().contains("3f1c9a0e2b7d4c58"));
+    }
+
+    #[test]
+    fn damaged_legacy_header_is_rejected() {
+        let encrypted = format!("{}\nscheme: age-x25519", HEADER_MAGIC).into_bytes();
+        let err = decrypt_bytes(None, None, &encrypted).err().unwrap();
+        assert_eq!(err.get_err_msg(), ErrBadHeader.get_err_msg());
+    }
+}
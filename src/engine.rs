@@ -0,0 +1,484 @@
+use crate::{
+    cancel::{CancelReason, CancelToken},
+    config::BackupDescription,
+    error::{BackyError, BackyResult},
+    logging::{log, warning},
+    manifest::hash_file,
+    progress::ProgressHandle,
+    transfer::TransferIssues,
+};
+
+use filetime::FileTime;
+use glob::{MatchOptions, Pattern};
+use std::{
+    fs::{self, Metadata},
+    io,
+    os::unix::fs::{lchown, symlink, MetadataExt},
+    path::{Path, PathBuf},
+};
+
+// #######################
+//   Definições públicas
+// #######################
+/// Prefixo do diretório para onde um backup refeito no mesmo dia é movido
+/// enquanto o novo é criado
+pub const PREVIOUS_PREFIX: &str = ".backy-previous-";
+
+/// Cria um snapshot incremental de `source` em `dest` sem depender do rsync.
+///
+/// Arquivos que não mudaram desde `link_dest` (mesmo tamanho, mtime, permissões
+/// e, opcionalmente, mesmo conteúdo) são ligados por hardlink ao snapshot
+/// anterior; os demais são copiados. O resultado tem o mesmo layout que o
+/// `rsync -a --link-dest` produziria. Arquivos que não puderam ser copiados não
+/// interrompem o snapshot, e são devolvidos para que a política do backup
+/// decida o que fazer.
+pub fn create_snapshot(
+    source: &Path,
+    dest: &Path,
+    link_dest: Option<&Path>,
+    desc: &BackupDescription,
+    progress: ProgressHandle,
+    token: &CancelToken,
+) -> BackyResult<TransferIssues> {
+    let excludes = parse_excludes(desc)?;
+    let mut link_dest = link_dest.filter(|p| p.is_dir()).map(Path::to_owned);
+
+    // NOTE: se o backup for refeito no mesmo dia, o snapshot anterior fica no
+    // caminho de destino (e normalmente é o próprio `link_dest`). Ele é movido
+    // para o lado para servir de referência e removido ao final.
+    let previous = dest.with_file_name(format!(
+        "{}{}",
+        PREVIOUS_PREFIX,
+        dest.file_name().unwrap_or_default().to_string_lossy()
+    ));
+    if previous.exists() {
+        recover_previous(dest, &previous)?;
+    }
+    let had_previous = dest.exists();
+    if had_previous {
+        let points_to_dest = link_dest
+            .as_ref()
+            .map(|p| fs::canonicalize(p).ok() == fs::canonicalize(dest).ok())
+            .unwrap_or(false);
+        if let Err(err) = fs::rename(dest, &previous) {
+            return Err(Box::new(ErrEngineFatal::new(dest, err)));
+        }
+        if points_to_dest {
+            link_dest = Some(previous.clone());
+        }
+    }
+
+    let engine = Engine {
+        source,
+        dest,
+        link_dest: link_dest.as_deref(),
+        excludes,
+        checksum: desc.checksum,
+        progress,
+        token,
+        cancelled: None,
+        failures: Vec::new(),
+    };
+    let result = engine.run();
+    if had_previous {
+        match &result {
+            Ok(_) => {
+                fs::remove_dir_all(&previous).ok();
+            }
+            // NOTE: um backup que falhou não pode substituir o anterior, que
+            // estava completo
+            Err(_) => restore_previous(dest, &previous),
+        }
+    }
+    result
+}
+
+/// Quantidade de arquivos regulares e bytes encontrados em uma origem
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SourceStats {
+    pub files: u64,
+    pub bytes: u64,
+    /// Entradas (arquivos ou diretórios) que não puderam ser lidas
+    pub unreadable: u64,
+}
+
+/// Percorre a origem de um backup (respeitando os padrões de exclusão) e conta
+/// os arquivos regulares e o total de bytes que entrariam no snapshot
+pub fn scan_source(desc: &BackupDescription) -> BackyResult<SourceStats> {
+    let excludes = parse_excludes(desc)?;
+    let mut stats = SourceStats::default();
+    let mut pending = vec![PathBuf::new()];
+    while let Some(rel) = pending.pop() {
+        let dir = desc.backup_root.join(&rel);
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            // NOTE: só a raiz ilegível impede a contagem; um subdiretório
+            // ilegível é pulado, como no backup
+            Err(err) if rel.as_os_str().is_empty() => {
+                return Err(Box::new(ErrEngineFatal::new(&dir, err)))
+            }
+            Err(_) => {
+                stats.unreadable += 1;
+                continue;
+            }
+        };
+        for entry in entries.filter_map(|entry| entry.ok()) {
+            let rel = rel.join(entry.file_name());
+            let meta = match entry.metadata() {
+                Ok(meta) => meta,
+                Err(_) => {
+                    stats.unreadable += 1;
+                    continue;
+                }
+            };
+            if excludes
+                .iter()
+                .any(|rule| rule.matches(&rel, meta.is_dir()))
+            {
+                continue;
+            }
+            if meta.is_dir() {
+                pending.push(rel);
+            } else if meta.is_file() {
+                stats.files += 1;
+                stats.bytes += meta.len();
+            }
+        }
+    }
+    Ok(stats)
+}
+
+// #######################
+//   Definições privadas
+// #######################
+/// Trata a cópia anterior deixada por uma execução interrompida à força. Sem o
+/// destino, ela é a única cópia do backup e volta para o lugar; com ele, é
+/// descartada.
+fn recover_previous(dest: &Path, previous: &Path) -> BackyResult<()> {
+    let result = match dest.exists() {
+        false => fs::rename(previous, dest),
+        true => {
+            warning!(
+                "Removing '{}', left by an interrupted backup.",
+                previous.display()
+            );
+            fs::remove_dir_all(previous)
+        }
+    };
+    result.map_err(|err| Box::new(ErrEngineFatal::new(previous, err)) as Box<dyn BackyError>)
+}
+
+/// Devolve a cópia anterior ao destino, no lugar do snapshot que falhou. Se
+/// não for possível, a cópia é mantida onde está.
+fn restore_previous(dest: &Path, previous: &Path) {
+    let restored = match dest.exists() {
+        true => fs::remove_dir_all(dest),
+        false => Ok(()),
+    }
+    .and_then(|_| fs::rename(previous, dest));
+    if let Err(err) = restored {
+        warning!(
+            "Unable to restore the previous copy of the backup ({}). It was kept at '{}'.",
+            err,
+            previous.display()
+        );
+    }
+}
+
+/// Transforma os padrões `exclude_files` do backup em regras de exclusão
+fn parse_excludes(desc: &BackupDescription) -> BackyResult<Vec<ExcludeRule>> {
+    match &desc.exclude_files {
+        Some(patterns) => patterns.iter().map(|p| ExcludeRule::new(p)).collect(),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// Estado de uma execução do motor de snapshots
+struct Engine<'a> {
+    source: &'a Path,
+    dest: &'a Path,
+    link_dest: Option<&'a Path>,
+    excludes: Vec<ExcludeRule>,
+    checksum: bool,
+    progress: ProgressHandle,
+    token: &'a CancelToken,
+    /// Motivo do cancelamento, caso o snapshot tenha sido interrompido
+    cancelled: Option<CancelReason>,
+    /// Arquivos que não puderam ser transferidos, junto com o motivo
+    failures: Vec<(PathBuf, io::Error)>,
+}
+
+impl<'a> Engine<'a> {
+    fn run(mut self) -> BackyResult<TransferIssues> {
+        let root_meta = match fs::metadata(self.source) {
+            Ok(meta) => meta,
+            Err(err) => return Err(Box::new(ErrEngineFatal::new(self.source, err))),
+        };
+        if let Err(err) = fs::create_dir_all(self.dest) {
+            return Err(Box::new(ErrEngineFatal::new(self.dest, err)));
+        }
+        self.copy_dir(Path::new(""));
+        if let Some(reason) = self.cancelled {
+            let name = self.dest.file_name().unwrap_or_default().to_string_lossy();
+            return Err(reason.into_error(&name));
+        }
+        // NOTE: assim como o rsync com a '/' no final da origem, os atributos
+        // da raíz do backup são aplicados ao diretório de destino
+        if let Err(err) = copy_attributes(&root_meta, self.dest) {
+            self.failures.push((PathBuf::new(), err));
+        }
+
+        let mut issues = TransferIssues::default();
+        for (rel, err) in self.failures {
+            let file = format!("{}: {}", self.source.join(rel).display(), err);
+            if err.kind() == io::ErrorKind::NotFound {
+                issues.vanished.push(file);
+            } else {
+                issues.partial.push(file);
+            }
+        }
+        Ok(issues)
+    }
+
+    /// Copia recursivamente o diretório `rel` (relativo à origem)
+    fn copy_dir(&mut self, rel: &Path) {
+        let entries = match fs::read_dir(self.source.join(rel)) {
+            Ok(entries) => entries,
+            Err(err) => return self.failures.push((rel.to_owned(), err)),
+        };
+        for entry in entries {
+            if self.cancelled.is_some() {
+                return;
+            }
+            if let Some(reason) = self.token.check() {
+                self.cancelled = Some(reason);
+                return;
+            }
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(err) => {
+                    self.failures.push((rel.to_owned(), err));
+                    continue;
+                }
+            };
+            let rel = rel.join(entry.file_name());
+            if let Err(err) = self.copy_entry(&rel) {
+                self.failures.push((rel, err));
+            }
+        }
+    }
+
+    /// Copia uma única entrada (arquivo, diretório ou link) para o destino
+    fn copy_entry(&mut self, rel: &Path) -> io::Result<()> {
+        let src = self.source.join(rel);
+        let dst = self.dest.join(rel);
+        let meta = fs::symlink_metadata(&src)?;
+        let file_type = meta.file_type();
+        if self.is_excluded(rel, file_type.is_dir()) {
+            return Ok(());
+        }
+
+        if file_type.is_dir() {
+            fs::create_dir(&dst)?;
+            self.copy_dir(rel);
+            copy_attributes(&meta, &dst)
+        } else if file_type.is_symlink() {
+            symlink(fs::read_link(&src)?, &dst)?;
+            lchown(&dst, Some(meta.uid()), Some(meta.gid())).ok();
+            let mtime = FileTime::from_last_modification_time(&meta);
+            filetime::set_symlink_file_times(&dst, mtime, mtime)
+        } else if file_type.is_file() {
+            self.progress.current_file(&rel.to_string_lossy());
+            match self.unchanged_in_link_dest(rel, &src, &meta) {
+                Some(previous) => fs::hard_link(previous, &dst),
+                None => {
+                    fs::copy(&src, &dst)?;
+                    copy_attributes(&meta, &dst)
+                }
+            }
+        } else {
+            warning!("Skipping special file '{}'.", src.display());
+            Ok(())
+        }
+    }
+
+    /// Devolve o caminho da cópia anterior de `rel` caso ela seja idêntica ao
+    /// arquivo atual
+    fn unchanged_in_link_dest(&self, rel: &Path, src: &Path, meta: &Metadata) -> Option<PathBuf> {
+        let previous = self.link_dest?.join(rel);
+        let prev_meta = fs::symlink_metadata(&previous).ok()?;
+        let same_attrs = prev_meta.is_file()
+            && prev_meta.len() == meta.len()
+            && prev_meta.mode() == meta.mode()
+            && prev_meta.uid() == meta.uid()
+            && prev_meta.gid() == meta.gid()
+            && FileTime::from_last_modification_time(&prev_meta)
+                == FileTime::from_last_modification_time(meta);
+        if !same_attrs {
+            return None;
+        }
+        if self.checksum && hash_file(src).ok()? != hash_file(&previous).ok()? {
+            return None;
+        }
+        Some(previous)
+    }
+
+    fn is_excluded(&self, rel: &Path, is_dir: bool) -> bool {
+        self.excludes.iter().any(|rule| rule.matches(rel, is_dir))
+    }
+}
+
+/// Aplica dono, permissões e data de modificação de `meta` ao caminho `dst`
+fn copy_attributes(meta: &Metadata, dst: &Path) -> io::Result<()> {
+    // NOTE: assim como o rsync, só é possível preservar o dono quando o
+    // programa roda como super-usuário, então falhas aqui são ignoradas
+    lchown(dst, Some(meta.uid()), Some(meta.gid())).ok();
+    fs::set_permissions(dst, meta.permissions())?;
+    filetime::set_file_mtime(dst, FileTime::from_last_modification_time(meta))
+}
+
+/// Regra de exclusão com a mesma semântica dos padrões `--exclude` do rsync
+struct ExcludeRule {
+    pattern: Pattern,
+    /// O padrão começa com '/', e só casa a partir da raíz do backup
+    anchored: bool,
+    /// O padrão contém '/' ou '**', e deve casar com o caminho completo
+    full_path: bool,
+    /// O padrão termina com '/', e só casa com diretórios
+    dir_only: bool,
+}
+
+impl ExcludeRule {
+    fn new(raw: &str) -> BackyResult<Self> {
+        let dir_only = raw.ends_with('/') && raw.len() > 1;
+        let trimmed = raw.trim_end_matches('/');
+        let anchored = trimmed.starts_with('/');
+        let trimmed = trimmed.trim_start_matches('/');
+        let full_path = anchored || trimmed.contains('/') || trimmed.contains("**");
+        match Pattern::new(trimmed) {
+            Ok(pattern) => Ok(Self {
+                pattern,
+                anchored,
+                full_path,
+                dir_only,
+            }),
+            Err(_) => Err(Box::new(ErrBadExcludePattern {
+                pattern: raw.to_string(),
+            })),
+        }
+    }
+
+    fn matches(&self, rel: &Path, is_dir: bool) -> bool {
+        const OPTIONS: MatchOptions = MatchOptions {
+            case_sensitive: true,
+            require_literal_separator: true,
+            require_literal_leading_dot: false,
+        };
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        if !self.full_path {
+            return rel
+                .file_name()
+                .map(|name| self.pattern.matches_with(&name.to_string_lossy(), OPTIONS))
+                .unwrap_or(false);
+        }
+        if self.anchored {
+            return self.pattern.matches_path_with(rel, OPTIONS);
+        }
+        // Padrões não ancorados podem casar com qualquer sufixo do caminho
+        let components: Vec<_> = rel.components().collect();
+        (0..components.len()).any(|start| {
+            let suffix: PathBuf = components[start..].iter().collect();
+            self.pattern.matches_path_with(&suffix, OPTIONS)
+        })
+    }
+}
+
+// #######################
+//         Erros
+// #######################
+/// Erro lançado quando um padrão de `exclude_files` não é um glob válido
+struct ErrBadExcludePattern {
+    pattern: String,
+}
+impl BackyError for ErrBadExcludePattern {
+    fn get_err_msg(&self) -> String {
+        format!("invalid exclude pattern '{}'", self.pattern)
+    }
+}
+
+/// Erro lançado quando o motor não consegue nem começar a criar o snapshot
+struct ErrEngineFatal {
+    path: PathBuf,
+    err: io::Error,
+}
+impl ErrEngineFatal {
+    fn new(path: &Path, err: io::Error) -> Self {
+        Self {
+            path: path.to_owned(),
+            err,
+        }
+    }
+}
+impl BackyError for ErrEngineFatal {
+    fn get_err_msg(&self) -> String {
+        format!(
+            "unable to create snapshot at '{}':\n{}",
+            self.path.display(),
+            self.err
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(raw: &str) -> ExcludeRule {
+        ExcludeRule::new(raw).unwrap_or_else(|err| panic!("{}", err.get_err_msg()))
+    }
+
+    #[test]
+    fn plain_patterns_match_the_name_at_any_depth() {
+        let rule = rule("*.log");
+        assert!(rule.matches(Path::new("app.log"), false));
+        assert!(rule.matches(Path::new("var/app.log"), false));
+        assert!(!rule.matches(Path::new("logs/app.txt"), false));
+        assert!(!rule.matches(Path::new("app.log.gz"), false));
+    }
+
+    #[test]
+    fn anchored_patterns_match_only_from_the_root() {
+        let rule = rule("/cache");
+        assert!(rule.matches(Path::new("cache"), true));
+        assert!(!rule.matches(Path::new("home/cache"), true));
+    }
+
+    #[test]
+    fn patterns_with_slashes_match_path_suffixes() {
+        let rule = rule("build/*.o");
+        assert!(rule.matches(Path::new("build/main.o"), false));
+        assert!(rule.matches(Path::new("src/build/main.o"), false));
+        assert!(!rule.matches(Path::new("build/sub/main.o"), false));
+    }
+
+    #[test]
+    fn double_star_crosses_directories() {
+        let rule = rule("node_modules/**");
+        assert!(rule.matches(Path::new("node_modules/a/b.js"), false));
+        assert!(rule.matches(Path::new("web/node_modules/a.js"), false));
+    }
+
+    #[test]
+    fn trailing_slash_matches_only_directories() {
+        let rule = rule("tmp/");
+        assert!(rule.matches(Path::new("a/tmp"), true));
+        assert!(!rule.matches(Path::new("a/tmp"), false));
+    }
+
+    #[test]
+    fn invalid_patterns_are_rejected() {
+        assert!(ExcludeRule::new("[a-").is_err());
+    }
+}
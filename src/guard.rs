@@ -0,0 +1,139 @@
+use crate::{
+    config::{BackupDescription, ByteSize, SourceGuard},
+    engine,
+    error::{BackyError, BackyResult},
+    logging::{log, warning},
+    manifest::{self, Manifest},
+};
+
+use std::{fs, os::unix::fs::MetadataExt, path::Path};
+
+// #######################
+//   Definições públicas
+// #######################
+/// Confere se a origem do backup `name` satisfaz as condições configuradas
+/// pelo usuário. `previous_snapshot` é o snapshot usado como base para medir
+/// quanto a origem encolheu.
+pub fn check_source(
+    name: &str,
+    desc: &BackupDescription,
+    previous_snapshot: Option<&Path>,
+) -> BackyResult<()> {
+    let guard = match &desc.guard {
+        Some(guard) => guard,
+        None => return Ok(()),
+    };
+    let fail = |reason: String| -> BackyResult<()> {
+        Err(Box::new(ErrSourceGuard {
+            name: name.to_string(),
+            reason,
+        }))
+    };
+
+    if guard.require_mountpoint && !is_mountpoint(&desc.backup_root) {
+        return fail(format!(
+            "'{}' is not a mountpoint (maybe the disk isn't mounted?)",
+            desc.backup_root.display()
+        ));
+    }
+    if let Some(marker) = &guard.marker_file {
+        if !desc.backup_root.join(marker).exists() {
+            return fail(format!(
+                "marker file '{}' was not found in '{}'",
+                marker.display(),
+                desc.backup_root.display()
+            ));
+        }
+    }
+
+    if !needs_scan(guard) {
+        return Ok(());
+    }
+    let stats = engine::scan_source(desc)?;
+    if stats.unreadable > 0 {
+        warning!(
+            "{} entries of '{}' could not be read and were not counted by the source checks.",
+            stats.unreadable,
+            name
+        );
+    }
+    if let Some(min_files) = guard.min_files {
+        if stats.files < min_files {
+            return fail(format!(
+                "source has {} file(s), below the minimum of {}",
+                stats.files, min_files
+            ));
+        }
+    }
+    if let Some(min_size) = guard.min_size {
+        if ByteSize(stats.bytes) < min_size {
+            return fail(format!(
+                "source has {}, below the minimum of {}",
+                ByteSize(stats.bytes),
+                min_size
+            ));
+        }
+    }
+    if let Some(max_shrink) = guard.max_shrink {
+        let previous = previous_snapshot
+            .map(|snapshot| previous_file_count(name, snapshot))
+            .unwrap_or(0);
+        if previous > 0 && stats.files < previous {
+            let shrink = (previous - stats.files) * 100 / previous;
+            if shrink > max_shrink as u64 {
+                return fail(format!(
+                    "{}% of the files disappeared since the last snapshot ({} -> {}), above the limit of {}%",
+                    shrink, previous, stats.files, max_shrink
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+// #######################
+//   Definições privadas
+// #######################
+/// Checa se alguma das verificações exige percorrer a origem inteira
+fn needs_scan(guard: &SourceGuard) -> bool {
+    guard.min_files.is_some() || guard.min_size.is_some() || guard.max_shrink.is_some()
+}
+
+/// Checa se o caminho é um ponto de montagem, comparando o dispositivo dele com
+/// o do diretório pai
+fn is_mountpoint(path: &Path) -> bool {
+    let meta = match fs::metadata(path) {
+        Ok(meta) => meta,
+        Err(_) => return false,
+    };
+    match fs::metadata(path.join("..")) {
+        Ok(parent) => parent.dev() != meta.dev() || parent.ino() == meta.ino(),
+        Err(_) => false,
+    }
+}
+
+/// Número de arquivos do backup no snapshot anterior. Usa o manifesto quando
+/// ele existe, e conta os arquivos no disco caso contrário.
+fn previous_file_count(name: &str, previous_snapshot: &Path) -> u64 {
+    if let Ok(manifest) = Manifest::load(&Manifest::path(previous_snapshot, name)) {
+        return manifest.entries.len() as u64;
+    }
+    manifest::walk_files(&previous_snapshot.join(name))
+        .map(|files| files.len() as u64)
+        .unwrap_or(0)
+}
+
+// #######################
+//         Erros
+// #######################
+/// Erro lançado quando a origem de um backup não passa nas verificações de
+/// segurança, e o snapshot não deve ser criado
+struct ErrSourceGuard {
+    name: String,
+    reason: String,
+}
+impl BackyError for ErrSourceGuard {
+    fn get_err_msg(&self) -> String {
+        format!("refusing to create '{}' backup: {}", self.name, self.reason)
+    }
+}
@@ -0,0 +1,147 @@
+use crate::error::{BackyError, BackyResult};
+
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+// #######################
+//   Definições públicas
+// #######################
+/// Nome do arquivo (na raíz do arquivo de backups) com os envios aos remotes
+pub const LEDGER_FILE: &str = ".backy-uploads.toml";
+
+/// Formato das datas gravadas no registro
+pub const TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+/// Registro dos arquivos enviados com sucesso a cada remote
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Ledger {
+    /// Envios indexados pelo nome do remote, do mais antigo para o mais recente
+    #[serde(default)]
+    pub remotes: BTreeMap<String, Vec<Upload>>,
+}
+
+/// Um arquivo enviado a um remote
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Upload {
+    /// Nome do snapshot enviado
+    pub snapshot: String,
+    /// Nome do arquivo no remote (ou o nome base dos volumes)
+    pub file: String,
+    /// Data e hora em que o envio terminou
+    pub uploaded_at: String,
+    /// Número de volumes, se o arquivo foi dividido
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub volumes: Option<usize>,
+    /// SHA-256 do arquivo (de todos os volumes em sequência), calculado
+    /// durante a escrita
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    /// O conteúdo no remote foi conferido com o SHA-256 após o envio
+    #[serde(default)]
+    pub verified: bool,
+}
+
+impl Upload {
+    /// Data e hora do envio, se ela puder ser interpretada
+    pub fn time(&self) -> Option<NaiveDateTime> {
+        NaiveDateTime::parse_from_str(&self.uploaded_at, TIMESTAMP_FORMAT).ok()
+    }
+}
+
+impl Ledger {
+    /// Carrega o registro de envios do arquivo de backups. Um arquivo sem
+    /// registro é tratado como se nada tivesse sido enviado.
+    pub fn load(archive_path: &Path) -> BackyResult<Self> {
+        let path = archive_path.join(LEDGER_FILE);
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => {
+                return Err(Box::new(ErrLedgerRead {
+                    path,
+                    reason: err.to_string(),
+                }))
+            }
+        };
+        toml::from_str(&content).map_err(|err| {
+            Box::new(ErrLedgerRead {
+                path,
+                reason: err.to_string(),
+            }) as Box<dyn BackyError>
+        })
+    }
+
+    /// Grava o registro de envios no arquivo de backups. O arquivo é escrito ao
+    /// lado e renomeado, para nunca ficar pela metade.
+    pub fn save(&self, archive_path: &Path) -> BackyResult<()> {
+        let path = archive_path.join(LEDGER_FILE);
+        let tmp_path = archive_path.join(format!("{}.tmp", LEDGER_FILE));
+        let write = || -> io::Result<()> {
+            let content = toml::to_string(self)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            fs::write(&tmp_path, content)?;
+            fs::rename(&tmp_path, &path)
+        };
+        write().map_err(|err| {
+            fs::remove_file(&tmp_path).ok();
+            Box::new(ErrLedgerWrite { path, err }) as Box<dyn BackyError>
+        })
+    }
+
+    /// Registra um envio, substituindo um registro anterior do mesmo arquivo
+    pub fn record(&mut self, remote: &str, upload: Upload) {
+        let uploads = self.remotes.entry(remote.to_string()).or_default();
+        uploads.retain(|previous| previous.file != upload.file);
+        uploads.push(upload);
+    }
+
+    /// Esquece o envio de um arquivo que foi apagado do remote
+    pub fn forget(&mut self, remote: &str, file: &str) {
+        if let Some(uploads) = self.remotes.get_mut(remote) {
+            uploads.retain(|upload| upload.file != file);
+        }
+    }
+
+    /// Envios feitos a um remote
+    pub fn uploads(&self, remote: &str) -> &[Upload] {
+        self.remotes.get(remote).map_or(&[], Vec::as_slice)
+    }
+}
+
+// #######################
+//         Erros
+// #######################
+/// Erro lançado quando o registro de envios existe mas não pode ser lido
+struct ErrLedgerRead {
+    path: PathBuf,
+    reason: String,
+}
+impl BackyError for ErrLedgerRead {
+    fn get_err_msg(&self) -> String {
+        format!(
+            "unable to read the upload ledger from '{}':\n{}",
+            self.path.display(),
+            self.reason
+        )
+    }
+}
+
+/// Erro lançado quando não é possível gravar o registro de envios
+struct ErrLedgerWrite {
+    path: PathBuf,
+    err: io::Error,
+}
+impl BackyError for ErrLedgerWrite {
+    fn get_err_msg(&self) -> String {
+        format!(
+            "unable to save the upload ledger to '{}':\n{}",
+            self.path.display(),
+            self.err
+        )
+    }
+}
@@ -5,7 +5,7 @@ macro_rules! log {
     (level $lvl:expr, color $col:ident, $msg:expr) => {
         {
             use colored::Colorize as _;
-            println!("[{}] {}", $lvl.$col(), $msg);
+            $crate::progress::print_line(&format!("[{}] {}", $lvl.$col(), $msg));
         }
     };
 
@@ -13,7 +13,7 @@ macro_rules! log {
         {
             use colored::Colorize as _;
             let msg = format!($($arg),*);
-            println!("[{}] {}", $lvl.$col(), msg);
+            $crate::progress::print_line(&format!("[{}] {}", $lvl.$col(), msg));
         }
     };
 }
@@ -28,6 +28,16 @@ macro_rules! info {
     };
 }
 
+macro_rules! warning {
+    ( $msg:literal ) => {
+        log!(level "WARN", color yellow, $msg);
+    };
+
+    ( $($arg:expr),* ) => {
+        log!(level "WARN", color yellow, $($arg),*);
+    };
+}
+
 macro_rules! error {
     ( $msg:literal ) => {
         log!(level "ERROR", color red, $msg);
@@ -41,3 +51,4 @@ macro_rules! error {
 pub(crate) use error;
 pub(crate) use info;
 pub(crate) use log;
+pub(crate) use warning;
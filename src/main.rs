@@ -1,17 +1,33 @@
+mod archive;
+mod cancel;
+mod catalog;
+mod checksum;
 mod commands;
 mod config;
+mod crypt;
+mod engine;
 mod error;
+mod guard;
+mod ledger;
 mod logging;
+mod manifest;
+mod pack;
+mod pins;
+mod progress;
+mod rclone;
+mod retention;
+mod rsync;
+mod runner;
+mod transfer;
+mod volumes;
 
 use error::BackyResult;
 use std::{env, process::exit};
 
 fn run_app() -> BackyResult<()> {
     let args: Vec<String> = env::args().collect();
-    let config = config::load()?;
     let command = commands::from_args(&args)?;
-    command.execute(config)?;
-    Ok(())
+    command.run()
 }
 
 fn main() {
@@ -0,0 +1,362 @@
+use crate::{
+    archive::METADATA_DIR,
+    error::{BackyError, BackyResult},
+};
+
+use filetime::FileTime;
+use std::{
+    collections::BTreeMap,
+    ffi::OsString,
+    fs::{self, File, Metadata},
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    os::unix::{
+        ffi::{OsStrExt, OsStringExt},
+        fs::MetadataExt,
+    },
+    path::{Path, PathBuf},
+};
+
+// #######################
+//   Definições públicas
+// #######################
+/// Primeira linha de todo arquivo de manifesto, usada para identificar o formato
+const MANIFEST_HEADER: &str = "# backy manifest v1 blake3";
+
+/// Extensão dos arquivos de manifesto
+const MANIFEST_EXTENSION: &str = "manifest";
+
+/// Hashes do conteúdo de todos os arquivos regulares de um backup nomeado
+#[derive(Debug, Default)]
+pub struct Manifest {
+    /// Entradas indexadas pelo caminho relativo à raíz do backup
+    pub entries: BTreeMap<PathBuf, ManifestEntry>,
+}
+
+/// Informações registradas para cada arquivo de um backup
+#[derive(Debug, Clone)]
+pub struct ManifestEntry {
+    pub hash: blake3::Hash,
+    pub size: u64,
+    pub mtime: FileTime,
+    /// Inode do arquivo no momento em que o manifesto foi gerado. Permite
+    /// reaproveitar o hash de arquivos ligados por hardlink entre snapshots.
+    pub ino: u64,
+}
+
+impl Manifest {
+    /// Diretório onde ficam os manifestos do snapshot `snapshot_dir`
+    pub fn dir(snapshot_dir: &Path) -> PathBuf {
+        snapshot_dir.join(METADATA_DIR).join("manifests")
+    }
+
+    /// Caminho do manifesto do backup `name` dentro do snapshot `snapshot_dir`
+    pub fn path(snapshot_dir: &Path, name: &str) -> PathBuf {
+        Self::dir(snapshot_dir).join(format!("{}.{}", name, MANIFEST_EXTENSION))
+    }
+
+    /// Nomes dos backups que têm um manifesto registrado no snapshot
+    pub fn recorded_backups(snapshot_dir: &Path) -> Vec<String> {
+        let entries = match fs::read_dir(Self::dir(snapshot_dir)) {
+            Ok(entries) => entries,
+            Err(_) => return Vec::new(),
+        };
+        let mut names: Vec<String> = entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let path = entry.path();
+                if path.extension()? != MANIFEST_EXTENSION {
+                    return None;
+                }
+                Some(path.file_stem()?.to_str()?.to_string())
+            })
+            .collect();
+        names.sort();
+        names
+    }
+
+    /// Gera o manifesto de um backup recém criado. Hashes de arquivos que ainda
+    /// compartilham o inode com o manifesto `previous` são reaproveitados.
+    pub fn generate(backup_dir: &Path, previous: Option<&Manifest>) -> io::Result<Self> {
+        let mut entries = BTreeMap::new();
+        for (rel, meta) in walk_files(backup_dir)? {
+            let reused = previous
+                .and_then(|previous| previous.entries.get(&rel))
+                .filter(|entry| entry.matches_inode(&meta));
+            let hash = match reused {
+                Some(entry) => entry.hash,
+                None => hash_file(&backup_dir.join(&rel))?,
+            };
+            entries.insert(
+                rel,
+                ManifestEntry {
+                    hash,
+                    size: meta.len(),
+                    mtime: FileTime::from_last_modification_time(&meta),
+                    ino: meta.ino(),
+                },
+            );
+        }
+        Ok(Self { entries })
+    }
+
+    /// Lê um manifesto do disco
+    pub fn load(path: &Path) -> BackyResult<Self> {
+        let parse_err = |line: usize| -> Box<dyn BackyError> {
+            Box::new(ErrManifestCorrupted {
+                path: path.to_owned(),
+                line,
+            })
+        };
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(err) => return Err(Box::new(ErrManifestIo::new(path, err))),
+        };
+        let mut entries = BTreeMap::new();
+        for (idx, line) in BufReader::new(file).lines().enumerate() {
+            let line = match line {
+                Ok(line) => line,
+                Err(err) => return Err(Box::new(ErrManifestIo::new(path, err))),
+            };
+            if idx == 0 {
+                if line != MANIFEST_HEADER {
+                    return Err(parse_err(1));
+                }
+                continue;
+            }
+            let mut fields = line.splitn(5, ' ');
+            let mut next = || fields.next().ok_or_else(|| parse_err(idx + 1));
+            let hash = blake3::Hash::from_hex(next()?).map_err(|_| parse_err(idx + 1))?;
+            let size = next()?.parse().map_err(|_| parse_err(idx + 1))?;
+            let mtime = parse_mtime(next()?).ok_or_else(|| parse_err(idx + 1))?;
+            let ino = next()?.parse().map_err(|_| parse_err(idx + 1))?;
+            let rel = unescape_path(next()?);
+            entries.insert(
+                rel,
+                ManifestEntry {
+                    hash,
+                    size,
+                    mtime,
+                    ino,
+                },
+            );
+        }
+        Ok(Self { entries })
+    }
+
+    /// Escreve o manifesto no disco, criando os diretórios necessários
+    pub fn save(&self, path: &Path) -> BackyResult<()> {
+        let write = || -> io::Result<()> {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let mut out = BufWriter::new(File::create(path)?);
+            writeln!(out, "{}", MANIFEST_HEADER)?;
+            for (rel, entry) in &self.entries {
+                writeln!(
+                    out,
+                    "{} {} {}.{} {} {}",
+                    entry.hash.to_hex(),
+                    entry.size,
+                    entry.mtime.unix_seconds(),
+                    entry.mtime.nanoseconds(),
+                    entry.ino,
+                    escape_path(rel)
+                )?;
+            }
+            out.flush()
+        };
+        write().map_err(|err| Box::new(ErrManifestIo::new(path, err)) as Box<dyn BackyError>)
+    }
+}
+
+impl ManifestEntry {
+    /// Checa se o arquivo descrito por `meta` é o mesmo inode (inalterado)
+    /// registrado nesta entrada
+    pub fn matches_inode(&self, meta: &Metadata) -> bool {
+        self.ino == meta.ino()
+            && self.size == meta.len()
+            && self.mtime == FileTime::from_last_modification_time(meta)
+    }
+}
+
+/// Lista recursivamente todos os arquivos regulares abaixo de `root`, com
+/// caminhos relativos a ele
+pub fn walk_files(root: &Path) -> io::Result<Vec<(PathBuf, Metadata)>> {
+    let mut files = Vec::new();
+    let mut pending = vec![PathBuf::new()];
+    while let Some(rel) = pending.pop() {
+        for entry in fs::read_dir(root.join(&rel))? {
+            let entry = entry?;
+            let rel = rel.join(entry.file_name());
+            let meta = entry.metadata()?;
+            if meta.is_dir() {
+                pending.push(rel);
+            } else if meta.is_file() {
+                files.push((rel, meta));
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// Calcula o hash BLAKE3 do conteúdo de um arquivo
+pub fn hash_file(path: &Path) -> io::Result<blake3::Hash> {
+    let mut hasher = blake3::Hasher::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hasher.finalize())
+}
+
+// #######################
+//   Definições privadas
+// #######################
+fn parse_mtime(raw: &str) -> Option<FileTime> {
+    let (secs, nanos) = raw.split_once('.')?;
+    Some(FileTime::from_unix_time(
+        secs.parse().ok()?,
+        nanos.parse().ok()?,
+    ))
+}
+
+/// Escapa quebras de linha e barras invertidas para que cada entrada ocupe
+/// exatamente uma linha do manifesto. Bytes que não são UTF-8 válido viram
+/// `\xNN`, para que o nome original possa ser recuperado.
+fn escape_path(path: &Path) -> String {
+    let mut escaped = String::new();
+    for chunk in path.as_os_str().as_bytes().utf8_chunks() {
+        for c in chunk.valid().chars() {
+            match c {
+                '\\' => escaped.push_str("\\\\"),
+                '\n' => escaped.push_str("\\n"),
+                '\r' => escaped.push_str("\\r"),
+                c => escaped.push(c),
+            }
+        }
+        for byte in chunk.invalid() {
+            escaped.push_str(&format!("\\x{:02x}", byte));
+        }
+    }
+    escaped
+}
+
+fn unescape_path(raw: &str) -> PathBuf {
+    let mut path = Vec::with_capacity(raw.len());
+    let mut chars = raw.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            path.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+            continue;
+        }
+        match chars.next() {
+            Some('n') => path.push(b'\n'),
+            Some('r') => path.push(b'\r'),
+            Some('x') => {
+                let hex: String = chars.by_ref().take(2).collect();
+                match u8::from_str_radix(&hex, 16) {
+                    Ok(byte) => path.push(byte),
+                    Err(_) => path.extend_from_slice(format!("\\x{}", hex).as_bytes()),
+                }
+            }
+            Some(other) => path.extend_from_slice(other.encode_utf8(&mut [0; 4]).as_bytes()),
+            None => path.push(b'\\'),
+        }
+    }
+    PathBuf::from(OsString::from_vec(path))
+}
+
+// #######################
+//         Erros
+// #######################
+/// Erro lançado quando não é possível ler ou escrever um manifesto
+struct ErrManifestIo {
+    path: PathBuf,
+    err: io::Error,
+}
+impl ErrManifestIo {
+    fn new(path: &Path, err: io::Error) -> Self {
+        Self {
+            path: path.to_owned(),
+            err,
+        }
+    }
+}
+impl BackyError for ErrManifestIo {
+    fn get_err_msg(&self) -> String {
+        format!(
+            "unable to access manifest '{}':\n{}",
+            self.path.display(),
+            self.err
+        )
+    }
+}
+
+/// Erro lançado quando o conteúdo de um manifesto não pode ser interpretado
+struct ErrManifestCorrupted {
+    path: PathBuf,
+    line: usize,
+}
+impl BackyError for ErrManifestCorrupted {
+    fn get_err_msg(&self) -> String {
+        format!(
+            "manifest '{}' is corrupted (line {})",
+            self.path.display(),
+            self.line
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(raw: &[u8]) {
+        let path = PathBuf::from(OsString::from_vec(raw.to_vec()));
+        let escaped = escape_path(&path);
+        assert!(!escaped.contains('\n') && !escaped.contains('\r'));
+        assert_eq!(unescape_path(&escaped), path, "{}", escaped);
+    }
+
+    #[test]
+    fn escape_path_round_trips() {
+        round_trip(b"plain/file.txt");
+        round_trip("acentuação/日本.txt".as_bytes());
+        round_trip(b"back\\slash and\nnew\rline");
+        round_trip(b"literal \\x41 and \\n");
+        round_trip(b"caf\xe9/\xff\xfe.bin");
+    }
+
+    #[test]
+    fn escape_path_keeps_valid_utf8_readable() {
+        assert_eq!(escape_path(Path::new("dir/ação.txt")), "dir/ação.txt");
+        let path = PathBuf::from(OsString::from_vec(b"a\xe9\n".to_vec()));
+        assert_eq!(escape_path(&path), "a\\xe9\\n");
+    }
+
+    #[test]
+    fn manifest_survives_save_and_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("docs.manifest");
+        let name = PathBuf::from(OsString::from_vec(b"sub/caf\xe9 1.txt".to_vec()));
+        let entry = ManifestEntry {
+            hash: blake3::hash(b"content"),
+            size: 7,
+            mtime: FileTime::from_unix_time(1_700_000_000, 123),
+            ino: 42,
+        };
+        let manifest = Manifest {
+            entries: BTreeMap::from([(name.clone(), entry)]),
+        };
+        manifest
+            .save(&path)
+            .unwrap_or_else(|err| panic!("{}", err.get_err_msg()));
+
+        let loaded = Manifest::load(&path).unwrap_or_else(|err| panic!("{}", err.get_err_msg()));
+        let loaded_entry = &loaded.entries[&name];
+        assert_eq!(loaded_entry.hash, blake3::hash(b"content"));
+        assert_eq!(loaded_entry.size, 7);
+        assert_eq!(
+            loaded_entry.mtime,
+            FileTime::from_unix_time(1_700_000_000, 123)
+        );
+        assert_eq!(loaded_entry.ino, 42);
+    }
+}
@@ -0,0 +1,426 @@
+use crate::{
+    archive::Snapshot,
+    config::{CompressionFormat, CompressionSettings},
+    error::{BackyError, BackyResult},
+    logging::{log, warning},
+};
+
+use flate2::write::GzEncoder;
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, Write},
+    ops::RangeInclusive,
+    os::unix::fs::{FileTypeExt, MetadataExt},
+    path::{Path, PathBuf},
+    thread,
+};
+use tar::{Builder, EntryType, Header, HeaderMode};
+use xz2::write::XzEncoder;
+
+// #######################
+//   Definições públicas
+// #######################
+/// Extensão dos arquivos gerados com o formato de compressão
+pub fn extension(format: CompressionFormat) -> &'static str {
+    match format {
+        CompressionFormat::Gzip => "tar.gz",
+        CompressionFormat::Zstd => "tar.zst",
+        CompressionFormat::Xz => "tar.xz",
+    }
+}
+
+/// Escreve em `out` um arquivo tar comprimido com os backups do snapshot. O
+/// arquivo tem sempre o formato `<snapshot>/<backup>/...`, sem os metadados do
+/// backy.
+///
+/// Permissões, donos, datas e links simbólicos são preservados. Arquivos com
+/// vários hardlinks dentro do snapshot são gravados uma única vez, e os demais
+/// links viram entradas de hardlink. Atributos estendidos são gravados como
+/// cabeçalhos PAX (`SCHILY.xattr.*`) quando o sistema de arquivos os suporta.
+///
+/// Com `backups`, apenas os backups listados são incluídos.
+pub fn write_snapshot<W: Write>(
+    snapshot: &Snapshot,
+    settings: &CompressionSettings,
+    backups: Option<&[String]>,
+    out: W,
+) -> BackyResult<W> {
+    let encoder = Encoder::new(out, settings)?;
+    let mut packer = Packer {
+        builder: Builder::new(encoder),
+        links: HashMap::new(),
+    };
+    packer.builder.mode(HeaderMode::Complete);
+    packer.builder.follow_symlinks(false);
+    for member in members(snapshot, backups)? {
+        packer.append_tree(
+            &snapshot.path.join(&member),
+            &Path::new(&snapshot.name).join(&member),
+        )?;
+    }
+    let encoder = packer
+        .builder
+        .into_inner()
+        .map_err(|err| fail(&snapshot.path, err))?;
+    encoder.finish().map_err(|err| fail(&snapshot.path, err))
+}
+
+// #######################
+//   Definições privadas
+// #######################
+/// Nome do cabeçalho PAX com os atributos estendidos de uma entrada. O nome é
+/// apenas informativo.
+const PAX_HEADER_NAME: &str = "././@PaxHeader";
+
+/// Gera o arquivo tar, lembrando os inodes já gravados para representar os
+/// hardlinks
+struct Packer<W: Write> {
+    builder: Builder<Encoder<W>>,
+    /// (dispositivo, inode) -> caminho dentro do arquivo da primeira cópia
+    links: HashMap<(u64, u64), PathBuf>,
+}
+
+impl<W: Write> Packer<W> {
+    /// Adiciona `path` (e todo o seu conteúdo) ao arquivo com o nome `name`
+    fn append_tree(&mut self, path: &Path, name: &Path) -> BackyResult<()> {
+        let meta = fs::symlink_metadata(path).map_err(|err| fail(path, err))?;
+        let file_type = meta.file_type();
+        if file_type.is_socket() {
+            warning!("Skipping socket '{}'.", path.display());
+            return Ok(());
+        }
+
+        if !meta.is_dir() && meta.nlink() > 1 {
+            let key = (meta.dev(), meta.ino());
+            if let Some(target) = self.links.get(&key) {
+                let mut header = Header::new_gnu();
+                header.set_metadata_in_mode(&meta, HeaderMode::Complete);
+                header.set_entry_type(EntryType::Link);
+                header.set_size(0);
+                return self
+                    .builder
+                    .append_link(&mut header, name, target)
+                    .map_err(|err| fail(path, err));
+            }
+            self.links.insert(key, name.to_owned());
+        }
+
+        if !file_type.is_symlink() {
+            self.append_xattrs(path)?;
+        }
+        self.builder
+            .append_path_with_name(path, name)
+            .map_err(|err| fail(path, err))?;
+
+        if meta.is_dir() {
+            let mut children = Vec::new();
+            for entry in fs::read_dir(path).map_err(|err| fail(path, err))? {
+                children.push(entry.map_err(|err| fail(path, err))?.file_name());
+            }
+            children.sort();
+            for child in children {
+                self.append_tree(&path.join(&child), &name.join(&child))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Grava os atributos estendidos de `path` em um cabeçalho PAX, que se
+    /// aplica à próxima entrada do arquivo
+    fn append_xattrs(&mut self, path: &Path) -> BackyResult<()> {
+        // NOTE: sistemas de arquivos sem suporte a atributos estendidos não
+        // impedem a criação do arquivo
+        let names = match xattr::list(path) {
+            Ok(names) => names,
+            Err(_) => return Ok(()),
+        };
+        let mut records = Vec::new();
+        for attr in names {
+            if let Ok(Some(value)) = xattr::get(path, &attr) {
+                let key = format!("SCHILY.xattr.{}", attr.to_string_lossy());
+                records.extend(pax_record(&key, &value));
+            }
+        }
+        if records.is_empty() {
+            return Ok(());
+        }
+        let mut header = Header::new_ustar();
+        header.set_entry_type(EntryType::XHeader);
+        header.set_mode(0o644);
+        header.set_size(records.len() as u64);
+        self.builder
+            .append_data(&mut header, PAX_HEADER_NAME, records.as_slice())
+            .map_err(|err| fail(path, err))
+    }
+}
+
+/// Codifica um registro PAX (`<tamanho> <chave>=<valor>\n`), em que o tamanho
+/// inclui os próprios dígitos
+fn pax_record(key: &str, value: &[u8]) -> Vec<u8> {
+    let rest = key.len() + value.len() + 3;
+    let mut len = rest + rest.to_string().len();
+    while len != rest + len.to_string().len() {
+        len = rest + len.to_string().len();
+    }
+    let mut record = format!("{} {}=", len, key).into_bytes();
+    record.extend_from_slice(value);
+    record.push(b'\n');
+    record
+}
+
+/// Backups de um snapshot (diretórios que não são metadados do backy),
+/// limitados a `backups` se a lista existir
+fn members(snapshot: &Snapshot, backups: Option<&[String]>) -> BackyResult<Vec<PathBuf>> {
+    let entries = fs::read_dir(&snapshot.path).map_err(|err| fail(&snapshot.path, err))?;
+    let mut members = Vec::new();
+    for entry in entries {
+        let entry = entry.map_err(|err| fail(&snapshot.path, err))?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let is_backup = !name.starts_with('.')
+            && entry.file_type().is_ok_and(|t| t.is_dir())
+            && backups.is_none_or(|backups| backups.contains(&name));
+        if is_backup {
+            members.push(PathBuf::from(entry.file_name()));
+        }
+    }
+    members.sort();
+    Ok(members)
+}
+
+/// Compressor usado na saída do arquivo tar
+enum Encoder<W: Write> {
+    Gzip(GzEncoder<W>),
+    Zstd(zstd::Encoder<'static, W>),
+    Xz(XzEncoder<W>),
+}
+
+impl<W: Write> Encoder<W> {
+    fn new(out: W, settings: &CompressionSettings) -> BackyResult<Self> {
+        let format = settings.format;
+        match format {
+            CompressionFormat::Gzip => {
+                let level = check_level(format, settings.level, 6, 0..=9)?;
+                Ok(Encoder::Gzip(GzEncoder::new(
+                    out,
+                    flate2::Compression::new(level),
+                )))
+            }
+            CompressionFormat::Zstd => {
+                let level = check_level(format, settings.level, 3, 1..=22)?;
+                let threads = settings.threads.unwrap_or_else(|| {
+                    thread::available_parallelism().map_or(1, |n| n.get() as u32)
+                });
+                let mut encoder = zstd::Encoder::new(out, level as i32)
+                    .map_err(|err| fail_encoder(format, err))?;
+                encoder
+                    .multithread(threads)
+                    .map_err(|err| fail_encoder(format, err))?;
+                Ok(Encoder::Zstd(encoder))
+            }
+            CompressionFormat::Xz => {
+                let level = check_level(format, settings.level, 6, 0..=9)?;
+                Ok(Encoder::Xz(XzEncoder::new(out, level)))
+            }
+        }
+    }
+
+    /// Termina a compressão, devolvendo a saída
+    fn finish(self) -> io::Result<W> {
+        match self {
+            Encoder::Gzip(encoder) => encoder.finish(),
+            Encoder::Zstd(encoder) => encoder.finish(),
+            Encoder::Xz(encoder) => encoder.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Encoder::Gzip(encoder) => encoder.write(buf),
+            Encoder::Zstd(encoder) => encoder.write(buf),
+            Encoder::Xz(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Encoder::Gzip(encoder) => encoder.flush(),
+            Encoder::Zstd(encoder) => encoder.flush(),
+            Encoder::Xz(encoder) => encoder.flush(),
+        }
+    }
+}
+
+/// Confere se o nível configurado é válido para o formato, usando `default`
+/// quando ele não foi configurado
+fn check_level(
+    format: CompressionFormat,
+    level: Option<u32>,
+    default: u32,
+    range: RangeInclusive<u32>,
+) -> BackyResult<u32> {
+    let level = level.unwrap_or(default);
+    if range.contains(&level) {
+        return Ok(level);
+    }
+    Err(Box::new(ErrBadCompressionLevel {
+        format,
+        level,
+        range,
+    }))
+}
+
+fn fail(path: &Path, err: io::Error) -> Box<dyn BackyError> {
+    Box::new(ErrPack {
+        path: path.to_owned(),
+        err,
+    })
+}
+
+fn fail_encoder(format: CompressionFormat, err: io::Error) -> Box<dyn BackyError> {
+    Box::new(ErrEncoder { format, err })
+}
+
+// #######################
+//         Erros
+// #######################
+/// Erro lançado quando um arquivo do snapshot não pode ser adicionado ao
+/// arquivo comprimido
+struct ErrPack {
+    path: PathBuf,
+    err: io::Error,
+}
+impl BackyError for ErrPack {
+    fn get_err_msg(&self) -> String {
+        format!(
+            "unable to add '{}' to the compressed archive:\n{}",
+            self.path.display(),
+            self.err
+        )
+    }
+}
+
+/// Erro lançado quando o compressor não pode ser iniciado
+struct ErrEncoder {
+    format: CompressionFormat,
+    err: io::Error,
+}
+impl BackyError for ErrEncoder {
+    fn get_err_msg(&self) -> String {
+        format!(
+            "unable to start {:?} compression:\n{}",
+            self.format, self.err
+        )
+    }
+}
+
+/// Erro lançado quando o nível de compressão configurado não é válido para o
+/// formato escolhido
+struct ErrBadCompressionLevel {
+    format: CompressionFormat,
+    level: u32,
+    range: RangeInclusive<u32>,
+}
+impl BackyError for ErrBadCompressionLevel {
+    fn get_err_msg(&self) -> String {
+        format!(
+            "invalid {:?} compression level {} (expected {} to {})",
+            self.format,
+            self.level,
+            self.range.start(),
+            self.range.end()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    /// Tamanho declarado no início de um registro PAX
+    fn declared_len(record: &[u8]) -> usize {
+        let digits = record.iter().position(|&b| b == b' ').unwrap();
+        std::str::from_utf8(&record[..digits])
+            .unwrap()
+            .parse()
+            .unwrap()
+    }
+
+    #[test]
+    fn pax_record_encodes_key_and_value() {
+        assert_eq!(pax_record("a", b"b"), b"6 a=b\n");
+        assert_eq!(
+            pax_record("SCHILY.xattr.user.tag", b"x\0y"),
+            b"29 SCHILY.xattr.user.tag=x\0y\n"
+        );
+    }
+
+    #[test]
+    fn pax_record_length_includes_its_own_digits() {
+        // Cobre as passagens de 1 para 2 e de 2 para 3 dígitos no tamanho
+        for size in 0..200 {
+            let value = vec![b'v'; size];
+            let record = pax_record("k", &value);
+            assert_eq!(declared_len(&record), record.len(), "value size {}", size);
+            assert!(record.ends_with(b"v\n") || size == 0);
+        }
+    }
+
+    #[test]
+    fn xattrs_are_written_as_pax_records() {
+        let archive = tempfile::tempdir().unwrap();
+        let snapshot_path = archive.path().join("20261018");
+        let file = snapshot_path.join("docs/notes.txt");
+        fs::create_dir_all(file.parent().unwrap()).unwrap();
+        fs::write(&file, "hello").unwrap();
+        // NOTE: o sistema de arquivos dos testes pode não suportar atributos
+        // estendidos. O valor não tem '\n' porque o leitor de PAX do crate
+        // `tar` separa os registros por linha, e não pelo tamanho.
+        if xattr::set(&file, "user.backy.test", b"a=b c").is_err() {
+            return;
+        }
+        let snapshot = Snapshot {
+            name: "20261018".into(),
+            date: NaiveDate::from_ymd(2026, 10, 18),
+            path: snapshot_path,
+        };
+        let packed = write_snapshot(&snapshot, &CompressionSettings::default(), None, Vec::new())
+            .unwrap_or_else(|err| panic!("{}", err.get_err_msg()));
+
+        let mut tar = tar::Archive::new(GzDecoder::new(packed.as_slice()));
+        let mut found = None;
+        for entry in tar.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            if entry.path().unwrap() != Path::new("20261018/docs/notes.txt") {
+                continue;
+            }
+            let xattrs: Vec<(String, Vec<u8>)> = entry
+                .pax_extensions()
+                .unwrap()
+                .expect("missing PAX header")
+                .map(|ext| {
+                    let ext = ext.unwrap();
+                    (ext.key().unwrap().to_string(), ext.value_bytes().to_vec())
+                })
+                .filter(|(key, _)| key.starts_with("SCHILY.xattr.user."))
+                .collect();
+            let mut content = String::new();
+            entry.read_to_string(&mut content).unwrap();
+            found = Some((xattrs, content));
+        }
+        let (xattrs, content) = found.expect("file missing from the archive");
+        assert_eq!(
+            xattrs,
+            [(
+                "SCHILY.xattr.user.backy.test".to_string(),
+                b"a=b c".to_vec()
+            )]
+        );
+        assert_eq!(content, "hello");
+    }
+}
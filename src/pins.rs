@@ -0,0 +1,111 @@
+use crate::error::{BackyError, BackyResult};
+
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+// #######################
+//   Definições públicas
+// #######################
+/// Nome do arquivo (na raíz do arquivo de backups) com os snapshots fixados
+pub const PINS_FILE: &str = ".backy-pins.toml";
+
+/// Snapshots fixados pelo usuário, que nunca são removidos pelo `clean`
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Pins {
+    /// Fixações indexadas pelo nome do snapshot
+    #[serde(default)]
+    pub pins: BTreeMap<String, Pin>,
+}
+
+/// Informações sobre um snapshot fixado
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Pin {
+    /// Data e hora em que o snapshot foi fixado
+    pub pinned_at: String,
+    /// Anotação opcional do usuário (ex: o motivo da fixação)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+}
+
+impl Pins {
+    /// Carrega os snapshots fixados do arquivo de backups. Um arquivo sem
+    /// fixações é tratado como uma lista vazia.
+    pub fn load(archive_path: &Path) -> BackyResult<Self> {
+        let path = archive_path.join(PINS_FILE);
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => {
+                return Err(Box::new(ErrPinsRead {
+                    path,
+                    reason: err.to_string(),
+                }))
+            }
+        };
+        toml::from_str(&content).map_err(|err| {
+            Box::new(ErrPinsRead {
+                path,
+                reason: err.to_string(),
+            }) as Box<dyn BackyError>
+        })
+    }
+
+    /// Grava os snapshots fixados no arquivo de backups. O arquivo é escrito ao
+    /// lado e renomeado, para nunca ficar pela metade.
+    pub fn save(&self, archive_path: &Path) -> BackyResult<()> {
+        let path = archive_path.join(PINS_FILE);
+        let tmp_path = archive_path.join(format!("{}.tmp", PINS_FILE));
+        let write = || -> io::Result<()> {
+            let content = toml::to_string(self)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            fs::write(&tmp_path, content)?;
+            fs::rename(&tmp_path, &path)
+        };
+        write().map_err(|err| {
+            fs::remove_file(&tmp_path).ok();
+            Box::new(ErrPinsWrite { path, err }) as Box<dyn BackyError>
+        })
+    }
+
+    /// Checa se o snapshot está fixado
+    pub fn is_pinned(&self, snapshot: &str) -> bool {
+        self.pins.contains_key(snapshot)
+    }
+}
+
+// #######################
+//         Erros
+// #######################
+/// Erro lançado quando o arquivo de fixações existe mas não pode ser lido
+struct ErrPinsRead {
+    path: PathBuf,
+    reason: String,
+}
+impl BackyError for ErrPinsRead {
+    fn get_err_msg(&self) -> String {
+        format!(
+            "unable to read pinned snapshots from '{}':\n{}",
+            self.path.display(),
+            self.reason
+        )
+    }
+}
+
+/// Erro lançado quando não é possível gravar o arquivo de fixações
+struct ErrPinsWrite {
+    path: PathBuf,
+    err: io::Error,
+}
+impl BackyError for ErrPinsWrite {
+    fn get_err_msg(&self) -> String {
+        format!(
+            "unable to save pinned snapshots to '{}':\n{}",
+            self.path.display(),
+            self.err
+        )
+    }
+}
@@ -0,0 +1,308 @@
+use std::{
+    io::{self, IsTerminal, Write},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+// #######################
+//   Definições públicas
+// #######################
+/// Painel com o progresso de todos os backups que rodam em paralelo. Em um
+/// terminal, mostra uma linha por backup que é redesenhada continuamente; fora
+/// dele, escreve linhas de log periódicas.
+pub struct ProgressView {
+    renderer: Option<JoinHandle<()>>,
+    stop: Arc<AtomicBool>,
+}
+
+/// Referência para a linha de um backup no painel de progresso
+#[derive(Debug, Clone, Copy)]
+pub struct ProgressHandle {
+    index: usize,
+}
+
+impl ProgressView {
+    /// Cria o painel com uma linha para cada nome, devolvendo os handles na
+    /// mesma ordem
+    pub fn start(names: &[&str]) -> (Self, Vec<ProgressHandle>) {
+        let tty = io::stdout().is_terminal();
+        let rows = names
+            .iter()
+            .map(|name| Row {
+                name: name.to_string(),
+                ..Row::default()
+            })
+            .collect();
+        *lock_board() = Some(Board {
+            rows,
+            tty,
+            drawn: 0,
+        });
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let renderer = {
+            let stop = stop.clone();
+            thread::spawn(move || render_loop(&stop, tty))
+        };
+        let handles = (0..names.len())
+            .map(|index| ProgressHandle { index })
+            .collect();
+        let view = Self {
+            renderer: Some(renderer),
+            stop,
+        };
+        (view, handles)
+    }
+
+    /// Desenha o estado final do painel e o remove da tela
+    pub fn finish(mut self) {
+        self.stop_renderer();
+    }
+
+    fn stop_renderer(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(renderer) = self.renderer.take() {
+            renderer.join().ok();
+        }
+        let mut board = lock_board();
+        if let Some(board) = board.as_mut() {
+            if board.tty {
+                board.redraw();
+            }
+        }
+        *board = None;
+    }
+}
+
+impl Drop for ProgressView {
+    fn drop(&mut self) {
+        if self.renderer.is_some() {
+            self.stop_renderer();
+        }
+    }
+}
+
+impl ProgressHandle {
+    /// Atualiza o andamento da transferência
+    pub fn transfer(&self, percent: u8, rate: &str, eta: &str) {
+        self.update(|row| {
+            row.percent = Some(percent);
+            row.rate = rate.to_string();
+            row.eta = eta.to_string();
+        });
+    }
+
+    /// Atualiza o arquivo que está sendo transferido
+    pub fn current_file(&self, file: &str) {
+        self.update(|row| row.current = file.to_string());
+    }
+
+    /// Marca o backup como terminado
+    pub fn finish(&self, success: bool) {
+        self.update(|row| {
+            row.state = if success {
+                RowState::Done
+            } else {
+                RowState::Failed
+            };
+            row.current.clear();
+        });
+    }
+
+    fn update(&self, f: impl FnOnce(&mut Row)) {
+        if let Some(board) = lock_board().as_mut() {
+            if let Some(row) = board.rows.get_mut(self.index) {
+                f(row);
+            }
+        }
+    }
+}
+
+/// Escreve uma linha no terminal sem corromper o painel de progresso, caso ele
+/// esteja sendo mostrado
+pub fn print_line(line: &str) {
+    write_line(line, false);
+}
+
+/// Como [`print_line`], mas escreve a linha no stderr
+pub fn eprint_line(line: &str) {
+    write_line(line, true);
+}
+
+/// Checa se o painel está sendo redesenhado em um terminal
+pub fn is_interactive() -> bool {
+    matches!(lock_board().as_ref(), Some(board) if board.tty)
+}
+
+/// Uma linha de progresso do rsync (`--info=progress2`)
+pub struct RsyncProgress<'a> {
+    pub percent: u8,
+    pub rate: &'a str,
+    pub eta: &'a str,
+}
+
+/// Interpreta uma linha de progresso do rsync, no formato
+/// `  1,234,567  45%   12.34MB/s    0:01:23 (xfr#12, to-chk=3/100)`
+pub fn parse_rsync_progress(line: &str) -> Option<RsyncProgress<'_>> {
+    let mut fields = line.split_whitespace();
+    let bytes = fields.next()?;
+    if !bytes
+        .chars()
+        .all(|c| c.is_ascii_digit() || c == ',' || c == '.')
+    {
+        return None;
+    }
+    let percent = fields.next()?.strip_suffix('%')?.parse().ok()?;
+    let rate = fields.next()?;
+    let eta = fields.next()?;
+    Some(RsyncProgress { percent, rate, eta })
+}
+
+// #######################
+//   Definições privadas
+// #######################
+/// Escreve uma linha no stdout ou no stderr, apagando o painel antes e
+/// redesenhando-o depois
+fn write_line(line: &str, stderr: bool) {
+    let mut board = lock_board();
+    let board = match board.as_mut() {
+        Some(board) if board.tty => board,
+        _ if stderr => return eprintln!("{}", line),
+        _ => return println!("{}", line),
+    };
+    let clear = board.clear_sequence();
+    if stderr {
+        // NOTE: o painel é desenhado no stdout, que precisa ser esvaziado antes
+        // da linha aparecer no stderr
+        print!("{}", clear);
+        io::stdout().flush().ok();
+        eprintln!("{}", line);
+    } else {
+        println!("{}{}", clear, line);
+    }
+    board.drawn = 0;
+    board.redraw();
+}
+
+/// Intervalo entre redesenhos do painel em um terminal
+const TTY_TICK: Duration = Duration::from_millis(200);
+
+/// Intervalo entre linhas de log quando a saída não é um terminal
+const PLAIN_TICK: Duration = Duration::from_secs(30);
+
+/// Tamanho máximo do nome de arquivo mostrado, para que cada backup ocupe
+/// exatamente uma linha do terminal
+const MAX_FILE_WIDTH: usize = 48;
+
+static BOARD: Mutex<Option<Board>> = Mutex::new(None);
+
+fn lock_board() -> std::sync::MutexGuard<'static, Option<Board>> {
+    BOARD
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+struct Board {
+    rows: Vec<Row>,
+    tty: bool,
+    /// Número de linhas desenhadas na última atualização
+    drawn: usize,
+}
+
+#[derive(Default)]
+struct Row {
+    name: String,
+    percent: Option<u8>,
+    rate: String,
+    eta: String,
+    current: String,
+    state: RowState,
+}
+
+#[derive(Default, PartialEq, Eq)]
+enum RowState {
+    #[default]
+    Running,
+    Done,
+    Failed,
+}
+
+impl Board {
+    /// Sequência ANSI que apaga as linhas desenhadas anteriormente
+    fn clear_sequence(&self) -> String {
+        if self.drawn == 0 {
+            String::new()
+        } else {
+            format!("\x1b[{}A\x1b[J", self.drawn)
+        }
+    }
+
+    fn redraw(&mut self) {
+        let mut out = self.clear_sequence();
+        for row in &self.rows {
+            out.push_str(&row.render());
+            out.push('\n');
+        }
+        print!("{}", out);
+        io::stdout().flush().ok();
+        self.drawn = self.rows.len();
+    }
+}
+
+impl Row {
+    fn render(&self) -> String {
+        let percent = match self.percent {
+            Some(percent) => format!("{:>3}%", percent),
+            None => " --%".to_string(),
+        };
+        let status = match self.state {
+            RowState::Running => format!("{:>12}  ETA {:>9}", self.rate, self.eta),
+            RowState::Done => format!("{:>12}  {:>13}", "", "done"),
+            RowState::Failed => format!("{:>12}  {:>13}", "", "failed"),
+        };
+        format!(
+            "{:<16} {} {}  {}",
+            self.name,
+            percent,
+            status,
+            truncate_start(&self.current, MAX_FILE_WIDTH)
+        )
+    }
+}
+
+/// Remove o início de uma string muito longa, mantendo a parte final (que é a
+/// mais informativa em um caminho)
+fn truncate_start(text: &str, width: usize) -> String {
+    let len = text.chars().count();
+    if len <= width {
+        return text.to_string();
+    }
+    let tail: String = text.chars().skip(len - width + 3).collect();
+    format!("...{}", tail)
+}
+
+fn render_loop(stop: &AtomicBool, tty: bool) {
+    let mut last_plain = Instant::now();
+    while !stop.load(Ordering::SeqCst) {
+        thread::sleep(TTY_TICK);
+        let mut board = lock_board();
+        let board = match board.as_mut() {
+            Some(board) => board,
+            None => return,
+        };
+        if tty {
+            board.redraw();
+        } else if last_plain.elapsed() >= PLAIN_TICK {
+            last_plain = Instant::now();
+            for row in board.rows.iter().filter(|r| r.state == RowState::Running) {
+                // NOTE: o log! não pode ser usado aqui, já que o painel está
+                // bloqueado por este laço
+                println!("[INFO] {}", row.render().trim_end());
+            }
+        }
+    }
+}
@@ -0,0 +1,156 @@
+use crate::{
+    checksum,
+    error::{BackyError, BackyResult},
+    runner::{ErrProcess, Process},
+};
+
+use serde::Deserialize;
+use std::path::Path;
+
+// #######################
+//   Definições públicas
+// #######################
+/// Códigos de saída do rclone que não adianta repetir: erro de uso, diretório
+/// ou arquivo inexistente e erro fatal
+pub const PERMANENT_EXIT_CODES: [i32; 4] = [1, 3, 4, 7];
+
+/// Código de saída do rclone para um diretório que não existe
+pub const DIR_NOT_FOUND: i32 = 3;
+
+/// Uma entrada da listagem do `rclone lsjson`
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "PascalCase")]
+pub struct RemoteEntry {
+    pub name: String,
+    /// Tamanho em bytes, ou -1 se o remote não souber
+    pub size: i64,
+}
+
+/// Caminho de um arquivo dentro de um destino do rclone (`remote:` ou
+/// `remote:diretório`)
+pub fn join(target: &str, file_name: &str) -> String {
+    match target.ends_with(':') || target.ends_with('/') {
+        true => format!("{}{}", target, file_name),
+        false => format!("{}/{}", target, file_name),
+    }
+}
+
+/// Lista os arquivos de `target` (um diretório ou um único arquivo) com o
+/// `rclone lsjson`
+pub fn list(target: &str) -> BackyResult<Vec<RemoteEntry>> {
+    let output = Process::new("rclone")
+        .args(["lsjson", "--files-only"])
+        .arg(target)
+        .transient()
+        .permanent_codes(&PERMANENT_EXIT_CODES)
+        .run()
+        .map_err(|err| fail("lsjson", target, Reason::Process(err)))?;
+    serde_json::from_slice(&output.stdout)
+        .map_err(|err| fail("lsjson", target, Reason::Output(err.to_string())))
+}
+
+/// Copia o arquivo local `path` para o caminho `target` no remote
+pub fn copy_to(path: &Path, target: &str) -> BackyResult<()> {
+    Process::new("rclone")
+        .arg("copyto")
+        .arg(path)
+        .arg(target)
+        .transient()
+        .permanent_codes(&PERMANENT_EXIT_CODES)
+        .run()
+        .map(|_| ())
+        .map_err(|err| fail("copyto", target, Reason::Process(err)))
+}
+
+/// Apaga o arquivo `target` do remote
+pub fn delete(target: &str) -> BackyResult<()> {
+    Process::new("rclone")
+        .arg("deletefile")
+        .arg(target)
+        .transient()
+        .permanent_codes(&PERMANENT_EXIT_CODES)
+        .run()
+        .map(|_| ())
+        .map_err(|err| fail("deletefile", target, Reason::Process(err)))
+}
+
+/// Calcula o SHA-256 de um arquivo no remote com o `rclone hashsum`. Se o
+/// remote não calcular esse hash, o arquivo é baixado e o hash é calculado
+/// localmente.
+pub fn sha256(target: &str) -> BackyResult<String> {
+    match hashsum(target, false) {
+        Ok(Some(hash)) => Ok(hash),
+        _ => hashsum(target, true)?.ok_or_else(|| {
+            fail(
+                "hashsum",
+                target,
+                Reason::Output("the remote returned no SHA-256 hash".into()),
+            )
+        }),
+    }
+}
+
+// #######################
+//   Definições privadas
+// #######################
+/// Executa o `rclone hashsum sha256`, baixando o arquivo se `download` for
+/// verdadeiro. Devolve `None` se o remote não informar o hash.
+fn hashsum(target: &str, download: bool) -> BackyResult<Option<String>> {
+    let mut process = Process::new("rclone").args(["hashsum", "sha256"]);
+    if download {
+        // NOTE: sem o `--download`, uma falha geralmente indica que o remote
+        // não calcula o hash, e não vale a pena repetir
+        process = process
+            .arg("--download")
+            .transient()
+            .permanent_codes(&PERMANENT_EXIT_CODES);
+    }
+    let output = process
+        .arg(target)
+        .run()
+        .map_err(|err| fail("hashsum", target, Reason::Process(err)))?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let hash = stdout.split_whitespace().next().unwrap_or_default();
+    match checksum::is_sha256(hash) {
+        true => Ok(Some(hash.to_ascii_lowercase())),
+        false => Ok(None),
+    }
+}
+
+/// Por que um comando do rclone falhou
+enum Reason {
+    /// O rclone falhou
+    Process(ErrProcess),
+    /// O rclone terminou, mas a saída não é a esperada
+    Output(String),
+}
+
+fn fail(action: &'static str, target: &str, reason: Reason) -> Box<dyn BackyError> {
+    Box::new(ErrRclone {
+        action,
+        target: target.to_string(),
+        reason,
+    })
+}
+
+// #######################
+//         Erros
+// #######################
+/// Erro lançado quando um comando do rclone falha
+struct ErrRclone {
+    action: &'static str,
+    target: String,
+    reason: Reason,
+}
+impl BackyError for ErrRclone {
+    fn get_err_msg(&self) -> String {
+        let reason = match &self.reason {
+            Reason::Process(err) => err.get_err_msg(),
+            Reason::Output(reason) => reason.clone(),
+        };
+        format!(
+            "`rclone {}` failed for '{}':\n{}",
+            self.action, self.target, reason
+        )
+    }
+}
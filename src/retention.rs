@@ -0,0 +1,160 @@
+use crate::config::RetentionPolicy;
+
+use chrono::{Datelike, NaiveDate};
+
+// #######################
+//   Definições públicas
+// #######################
+/// Decide quais datas devem ser mantidas pela política de retenção. Devolve,
+/// para cada data (na mesma ordem da entrada), a lista de regras que a mantêm.
+/// Uma lista vazia significa que a data pode ser removida.
+///
+/// As regras funcionam como o `forget` do restic/borg: cada regra percorre as
+/// datas da mais recente para a mais antiga e mantém a primeira de cada período
+/// diferente, até atingir o número configurado de períodos.
+pub fn keep_reasons(dates: &[NaiveDate], policy: &RetentionPolicy) -> Vec<Vec<&'static str>> {
+    let mut order: Vec<usize> = (0..dates.len()).collect();
+    order.sort_by(|&a, &b| dates[b].cmp(&dates[a]));
+
+    let mut reasons = vec![Vec::new(); dates.len()];
+    for (rule, count, bucket) in rules(policy) {
+        let mut remaining = count;
+        let mut last_bucket = None;
+        for &idx in &order {
+            if remaining == 0 {
+                break;
+            }
+            let current = bucket(dates[idx]);
+            if last_bucket != Some(current) {
+                reasons[idx].push(rule);
+                last_bucket = Some(current);
+                remaining -= 1;
+            }
+        }
+    }
+    reasons
+}
+
+/// Política de retenção por idade: mantém as datas com menos de `max_days`
+/// dias em relação a `today`
+pub fn keep_reasons_by_age(
+    dates: &[NaiveDate],
+    today: NaiveDate,
+    max_days: i64,
+) -> Vec<Vec<&'static str>> {
+    dates
+        .iter()
+        .map(|&date| {
+            if (today - date).num_days() < max_days {
+                vec!["recent"]
+            } else {
+                Vec::new()
+            }
+        })
+        .collect()
+}
+
+// #######################
+//   Definições privadas
+// #######################
+/// Função que associa uma data ao seu período (dia, semana, mês ou ano)
+type Bucket = fn(NaiveDate) -> (i32, u32, u32);
+
+/// Regras configuradas, com o nome, o número de períodos e o agrupamento
+fn rules(policy: &RetentionPolicy) -> Vec<(&'static str, usize, Bucket)> {
+    // NOTE: existe no máximo um snapshot por dia, então a regra `last` pode
+    // tratar cada data como um período próprio
+    let all: [(&'static str, Option<usize>, Bucket); 5] = [
+        ("last", policy.keep_last, |d| (d.year(), d.ordinal(), 0)),
+        ("daily", policy.keep_daily, |d| {
+            (d.year(), d.month(), d.day())
+        }),
+        ("weekly", policy.keep_weekly, |d| {
+            let week = d.iso_week();
+            (week.year(), week.week(), 0)
+        }),
+        ("monthly", policy.keep_monthly, |d| (d.year(), d.month(), 0)),
+        ("yearly", policy.keep_yearly, |d| (d.year(), 0, 0)),
+    ];
+    all.into_iter()
+        .filter_map(|(rule, count, bucket)| Some((rule, count?, bucket)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    #[test]
+    fn keep_last_keeps_the_newest_dates_in_any_order() {
+        let dates = [date(2024, 1, 3), date(2024, 1, 1), date(2024, 1, 2)];
+        let policy = RetentionPolicy {
+            keep_last: Some(2),
+            ..Default::default()
+        };
+        let reasons = keep_reasons(&dates, &policy);
+        assert_eq!(reasons, vec![vec!["last"], vec![], vec!["last"]]);
+    }
+
+    #[test]
+    fn periodic_rules_keep_the_newest_date_of_each_period() {
+        // 2024-01-01 é uma segunda-feira, na mesma semana que 2024-01-03
+        let dates = [
+            date(2023, 12, 31),
+            date(2024, 1, 1),
+            date(2024, 1, 3),
+            date(2024, 1, 8),
+            date(2024, 2, 1),
+        ];
+        let policy = RetentionPolicy {
+            keep_weekly: Some(3),
+            keep_monthly: Some(2),
+            ..Default::default()
+        };
+        let reasons = keep_reasons(&dates, &policy);
+        assert_eq!(
+            reasons,
+            vec![
+                vec![],
+                vec![],
+                vec!["weekly"],
+                vec!["weekly", "monthly"],
+                vec!["weekly", "monthly"],
+            ]
+        );
+    }
+
+    #[test]
+    fn rules_are_combined_and_stop_at_their_count() {
+        let dates = [date(2022, 6, 1), date(2023, 6, 1), date(2024, 6, 1)];
+        let policy = RetentionPolicy {
+            keep_daily: Some(1),
+            keep_yearly: Some(2),
+            ..Default::default()
+        };
+        let reasons = keep_reasons(&dates, &policy);
+        assert_eq!(
+            reasons,
+            vec![vec![], vec!["yearly"], vec!["daily", "yearly"]]
+        );
+    }
+
+    #[test]
+    fn empty_policy_keeps_nothing() {
+        let dates = [date(2024, 1, 1), date(2024, 1, 2)];
+        let reasons = keep_reasons(&dates, &RetentionPolicy::default());
+        assert!(reasons.iter().all(Vec::is_empty));
+    }
+
+    #[test]
+    fn keep_by_age_excludes_the_limit_day() {
+        let today = date(2024, 1, 10);
+        let dates = [date(2024, 1, 3), date(2024, 1, 4), date(2024, 1, 10)];
+        let reasons = keep_reasons_by_age(&dates, today, 7);
+        assert_eq!(reasons, vec![vec![], vec!["recent"], vec!["recent"]]);
+    }
+}
@@ -0,0 +1,340 @@
+use crate::{
+    cancel::{self, CancelReason, CancelToken},
+    progress::{self, ProgressHandle},
+    runner,
+    transfer::TransferIssues,
+};
+
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{self, BufRead, BufReader, Read, Write},
+    os::unix::process::CommandExt,
+    process::{Command, ExitStatus, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+// #######################
+//   Definições públicas
+// #######################
+/// Resultado de uma execução do rsync, interpretado a partir do código de saída
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RsyncOutcome {
+    /// Todos os arquivos foram transferidos (código 0)
+    Success,
+    /// Alguns arquivos não puderam ser transferidos (código 23)
+    PartialTransfer,
+    /// Alguns arquivos desapareceram durante a transferência (código 24)
+    VanishedFiles,
+    /// Qualquer outro erro, que impede a criação do backup
+    Failure(Option<i32>),
+    /// O rsync foi terminado pelo backy antes de acabar
+    Cancelled(CancelReason),
+}
+
+impl RsyncOutcome {
+    pub fn from_status(status: ExitStatus) -> Self {
+        match status.code() {
+            Some(0) => RsyncOutcome::Success,
+            Some(23) => RsyncOutcome::PartialTransfer,
+            Some(24) => RsyncOutcome::VanishedFiles,
+            code => RsyncOutcome::Failure(code),
+        }
+    }
+}
+
+/// Descrição dos códigos de saída do rsync, como documentados no manual
+pub fn describe_exit_code(code: Option<i32>) -> &'static str {
+    match code {
+        Some(1) => "syntax or usage error",
+        Some(2) => "protocol incompatibility",
+        Some(3) => "errors selecting input/output files, dirs",
+        Some(4) => "requested action not supported",
+        Some(5) => "error starting client-server protocol",
+        Some(6) => "daemon unable to append to log-file",
+        Some(10) => "error in socket I/O",
+        Some(11) => "error in file I/O",
+        Some(12) => "error in rsync protocol data stream",
+        Some(13) => "errors with program diagnostics",
+        Some(14) => "error in IPC code",
+        Some(20) => "received SIGUSR1 or SIGINT",
+        Some(21) => "some error returned by waitpid()",
+        Some(22) => "error allocating core memory buffers",
+        Some(23) => "partial transfer due to error",
+        Some(24) => "partial transfer due to vanished source files",
+        Some(25) => "the --max-delete limit stopped deletions",
+        Some(30) => "timeout in data send/receive",
+        Some(35) => "timeout waiting for daemon connection",
+        None => "terminated by a signal",
+        _ => "unknown error",
+    }
+}
+
+/// Argumentos que fazem o rsync reportar o progresso total e os arquivos
+/// transferidos no stdout. Quando o painel é mostrado em um terminal, a lista de
+/// arquivos é montada antes da transferência, para que a porcentagem seja
+/// calculada sobre o total; fora dele, o rsync mantém a recursão incremental,
+/// que usa menos memória.
+pub fn progress_args() -> &'static [&'static str] {
+    if progress::is_interactive() {
+        &["--info=progress2,name1", "--no-inc-recursive"]
+    } else {
+        &["--info=progress2,name1"]
+    }
+}
+
+/// Número máximo de diretórios de `--link-dest` aceitos pelo rsync
+pub const MAX_LINK_DESTS: usize = 20;
+
+/// Número de linhas finais do stderr guardadas para as mensagens de erro
+pub const STDERR_TAIL_LINES: usize = 10;
+
+/// Resultado de uma execução completa do rsync
+pub struct RsyncRun {
+    pub outcome: RsyncOutcome,
+    /// Arquivos afetados por erros parciais
+    pub issues: TransferIssues,
+    /// Últimas linhas escritas no stderr
+    pub stderr_tail: Vec<String>,
+    /// Quanto tempo o rsync levou
+    pub duration: Duration,
+}
+
+/// Executa o rsync, enviando o progresso lido do stdout para o painel, repassando
+/// o stderr para o stderr do backy e coletando a lista de arquivos afetados por
+/// erros parciais. Toda a saída (exceto as linhas de progresso) é gravada em `log`.
+///
+/// O rsync roda em um grupo de processos próprio, que é terminado caso `token`
+/// seja cancelado.
+pub fn run(
+    command: &mut Command,
+    progress: ProgressHandle,
+    log: File,
+    token: &CancelToken,
+) -> io::Result<RsyncRun> {
+    write_log(&log, &format!("$ {}", runner::command_line(command)));
+    let started = Instant::now();
+    let mut child = command
+        .process_group(0)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let stdout_reader = child.stdout.take().map(|stdout| {
+        let log = log.try_clone();
+        thread::spawn(move || {
+            for_each_segment(stdout, |segment| {
+                if !report_progress(segment, progress) {
+                    if let Ok(log) = &log {
+                        write_log(log, segment);
+                    }
+                }
+            });
+        })
+    });
+    let stderr_reader = child.stderr.take().map(|stderr| {
+        let log = log.try_clone();
+        thread::spawn(move || {
+            let mut issues = TransferIssues::default();
+            let mut stderr_tail = VecDeque::with_capacity(STDERR_TAIL_LINES);
+            for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+                progress::eprint_line(&line);
+                if let Ok(log) = &log {
+                    write_log(log, &line);
+                }
+                collect_issue(&line, &mut issues);
+                if stderr_tail.len() == STDERR_TAIL_LINES {
+                    stderr_tail.pop_front();
+                }
+                stderr_tail.push_back(line);
+            }
+            (issues, stderr_tail)
+        })
+    });
+
+    let outcome = match cancel::wait_child(&mut child, token)? {
+        Ok(status) => {
+            write_log(&log, &format!("# rsync exited with {}", status));
+            RsyncOutcome::from_status(status)
+        }
+        Err(reason) => {
+            write_log(&log, &format!("# rsync was terminated: {}", reason));
+            RsyncOutcome::Cancelled(reason)
+        }
+    };
+    if let Some(reader) = stdout_reader {
+        reader.join().ok();
+    }
+    let (issues, stderr_tail) = stderr_reader
+        .and_then(|reader| reader.join().ok())
+        .unwrap_or_default();
+    Ok(RsyncRun {
+        issues: classify_issues(&outcome, issues),
+        outcome,
+        stderr_tail: stderr_tail.into(),
+        duration: started.elapsed(),
+    })
+}
+
+// #######################
+//   Definições privadas
+// #######################
+/// Mensagens de erro do rsync que se referem a um único arquivo, sem o prefixo
+/// `rsync: [<papel>] `. O nome do arquivo vem logo depois, entre aspas.
+const FILE_ERRORS: [&str; 5] = [
+    "send_files failed to open ",
+    "opendir ",
+    "read errors mapping ",
+    "readlink_stat(",
+    "recv_generator: failed to stat ",
+];
+
+/// Interpreta uma linha do stderr do rsync, registrando o arquivo afetado
+fn collect_issue(line: &str, issues: &mut TransferIssues) {
+    if let Some(rest) = line.strip_prefix("file has vanished: ") {
+        issues.vanished.push(unquote(rest).to_string());
+        return;
+    }
+    let message = match line.strip_prefix("rsync: ") {
+        Some(message) => message,
+        None => return,
+    };
+    // NOTE: versões mais novas indicam o processo que gerou a mensagem, como em
+    // `rsync: [sender] send_files failed to open "<arquivo>": <motivo>`
+    let message = match message.strip_prefix('[') {
+        Some(rest) => rest.split_once("] ").map_or(message, |(_, rest)| rest),
+        None => message,
+    };
+    // NOTE: outras mensagens com aspas (`link_stat`, `readdir`, ...) não
+    // identificam um arquivo que ficou de fora do snapshot
+    if !FILE_ERRORS.iter().any(|error| message.starts_with(error)) {
+        return;
+    }
+    if let Some(file) = message.split('"').nth(1) {
+        issues.partial.push(file.to_string());
+    }
+}
+
+/// Mantém apenas os problemas compatíveis com o código de saída do rsync: o
+/// código 24 indica que nenhum arquivo falhou além dos que desapareceram, e o
+/// código 0 que não houve problema algum
+fn classify_issues(outcome: &RsyncOutcome, mut issues: TransferIssues) -> TransferIssues {
+    match outcome {
+        RsyncOutcome::Success => TransferIssues::default(),
+        RsyncOutcome::VanishedFiles => {
+            issues.partial.clear();
+            issues
+        }
+        _ => issues,
+    }
+}
+
+/// Atualiza o painel com uma linha do stdout do rsync, que pode ser uma linha de
+/// progresso ou o nome de um arquivo transferido. Devolve se a linha era de
+/// progresso.
+fn report_progress(segment: &str, progress: ProgressHandle) -> bool {
+    match progress::parse_rsync_progress(segment) {
+        Some(p) => {
+            progress.transfer(p.percent, p.rate, p.eta);
+            true
+        }
+        None => {
+            progress.current_file(segment.trim());
+            false
+        }
+    }
+}
+
+/// Escreve uma linha no arquivo de log.
+// NOTE: a linha é escrita com uma única chamada, para que as escritas das
+// threads de stdout e stderr não se misturem
+fn write_log(mut log: &File, line: &str) {
+    log.write_all(format!("{}\n", line).as_bytes()).ok();
+}
+
+/// Lê `reader` até o fim, chamando `f` para cada trecho separado por '\n' ou
+/// '\r' (o rsync usa '\r' para sobrescrever a linha de progresso)
+fn for_each_segment(reader: impl Read, mut f: impl FnMut(&str)) {
+    let mut reader = BufReader::new(reader);
+    let mut segment = Vec::new();
+    loop {
+        let buf = match reader.fill_buf() {
+            Ok([]) | Err(_) => break,
+            Ok(buf) => buf,
+        };
+        let len = buf.len();
+        for &byte in buf {
+            if byte == b'\n' || byte == b'\r' {
+                if !segment.is_empty() {
+                    f(&String::from_utf8_lossy(&segment));
+                    segment.clear();
+                }
+            } else {
+                segment.push(byte);
+            }
+        }
+        reader.consume(len);
+    }
+    if !segment.is_empty() {
+        f(&String::from_utf8_lossy(&segment));
+    }
+}
+
+fn unquote(raw: &str) -> &str {
+    raw.trim().trim_matches('"')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn collect(lines: &[&str]) -> TransferIssues {
+        let mut issues = TransferIssues::default();
+        for line in lines {
+            collect_issue(line, &mut issues);
+        }
+        issues
+    }
+
+    #[test]
+    fn collects_per_file_errors() {
+        let issues = collect(&[
+            r#"rsync: [sender] send_files failed to open "/src/a.txt": Permission denied (13)"#,
+            r#"rsync: send_files failed to open "/src/b.txt": Permission denied (13)"#,
+            r#"rsync: [sender] opendir "/src/private" failed: Permission denied (13)"#,
+            r#"file has vanished: "/src/tmp/lock""#,
+        ]);
+        assert_eq!(issues.partial, ["/src/a.txt", "/src/b.txt", "/src/private"]);
+        assert_eq!(issues.vanished, ["/src/tmp/lock"]);
+    }
+
+    #[test]
+    fn ignores_messages_without_a_file() {
+        let issues = collect(&[
+            r#"rsync: [sender] readdir("/src/dir"): Input/output error (5)"#,
+            r#"rsync: link_stat "/src/missing" failed: No such file or directory (2)"#,
+            r#"rsync error: some files/attrs were not transferred (see previous errors) (code 23)"#,
+            "sending incremental file list",
+        ]);
+        assert!(issues.partial.is_empty());
+        assert!(issues.vanished.is_empty());
+    }
+
+    #[test]
+    fn classifies_issues_by_outcome() {
+        let lines = [
+            r#"rsync: [sender] send_files failed to open "/src/a.txt": Permission denied (13)"#,
+            r#"file has vanished: "/src/b.txt""#,
+        ];
+        let issues = classify_issues(&RsyncOutcome::VanishedFiles, collect(&lines));
+        assert!(issues.partial.is_empty());
+        assert_eq!(issues.vanished, ["/src/b.txt"]);
+
+        let issues = classify_issues(&RsyncOutcome::PartialTransfer, collect(&lines));
+        assert_eq!(issues.partial, ["/src/a.txt"]);
+        assert_eq!(issues.vanished, ["/src/b.txt"]);
+
+        let issues = classify_issues(&RsyncOutcome::Success, collect(&lines));
+        assert!(issues.partial.is_empty() && issues.vanished.is_empty());
+    }
+}
@@ -0,0 +1,428 @@
+use crate::{
+    error::BackyError,
+    logging::{log, warning},
+};
+
+use std::{
+    ffi::OsStr,
+    fmt,
+    io::{self, Read},
+    process::{Child, ChildStdin, Command, ExitStatus, Stdio},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+// #######################
+//   Definições públicas
+// #######################
+/// Número de tentativas de um comando transitório
+pub const TRANSIENT_ATTEMPTS: u32 = 3;
+
+/// Espera antes da segunda tentativa de um comando transitório. A espera dobra a
+/// cada nova tentativa.
+pub const RETRY_BACKOFF: Duration = Duration::from_secs(2);
+
+/// Um programa externo a ser executado. O stdout e o stderr são capturados, e
+/// qualquer falha (inclusive um código de saída diferente de zero) vira um
+/// [`ErrProcess`] com os detalhes da execução.
+pub struct Process {
+    command: Command,
+    attempts: u32,
+    /// Códigos de saída que indicam um erro que não adianta repetir
+    permanent_codes: &'static [i32],
+    interactive: bool,
+}
+
+/// Saída de uma execução bem-sucedida
+pub struct ProcessOutput {
+    pub stdout: Vec<u8>,
+}
+
+/// Um programa em execução que recebe dados pela entrada padrão
+pub struct RunningProcess {
+    child: Child,
+    command_line: String,
+    started: Instant,
+    stderr: Option<JoinHandle<String>>,
+}
+
+impl Process {
+    pub fn new(program: impl AsRef<OsStr>) -> Self {
+        Self {
+            command: Command::new(program),
+            attempts: 1,
+            permanent_codes: &[],
+            interactive: false,
+        }
+    }
+
+    pub fn arg(mut self, arg: impl AsRef<OsStr>) -> Self {
+        self.command.arg(arg);
+        self
+    }
+
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        self.command.args(args);
+        self
+    }
+
+    /// Marca o comando como sujeito a falhas passageiras (como os que acessam a
+    /// rede), que são repetidas algumas vezes com esperas crescentes
+    pub fn transient(mut self) -> Self {
+        self.attempts = TRANSIENT_ATTEMPTS;
+        self
+    }
+
+    /// Códigos de saída de um comando transitório que não são repetidos, por
+    /// indicarem erros permanentes (como um arquivo que não existe)
+    pub fn permanent_codes(mut self, codes: &'static [i32]) -> Self {
+        self.permanent_codes = codes;
+        self
+    }
+
+    /// Deixa o programa usar o terminal para interagir com o usuário (por
+    /// exemplo, para pedir uma senha). O stderr não é capturado.
+    pub fn interactive(mut self) -> Self {
+        self.interactive = true;
+        self
+    }
+
+    /// Linha de comando, como seria digitada no terminal
+    pub fn command_line(&self) -> String {
+        command_line(&self.command)
+    }
+
+    /// Executa o programa até o fim, repetindo-o se ele for transitório
+    pub fn run(mut self) -> Result<ProcessOutput, ErrProcess> {
+        let mut backoff = RETRY_BACKOFF;
+        let mut attempt = 1;
+        loop {
+            match self.run_once() {
+                Ok(output) => return Ok(output),
+                Err(mut err) if attempt >= self.attempts || !self.is_retriable(&err) => {
+                    err.attempts = attempt;
+                    return Err(err);
+                }
+                Err(err) => {
+                    warning!(
+                        "{}. Retrying in {}s ({}/{}).",
+                        err.summary(),
+                        backoff.as_secs(),
+                        attempt,
+                        self.attempts
+                    );
+                    thread::sleep(backoff);
+                    backoff *= 2;
+                    attempt += 1;
+                }
+            }
+        }
+    }
+
+    /// Inicia o programa com a entrada padrão ligada a um pipe. Comandos que
+    /// recebem dados pela entrada não são repetidos.
+    pub fn spawn(mut self) -> Result<RunningProcess, ErrProcess> {
+        let command_line = self.command_line();
+        let started = Instant::now();
+        let mut child = self
+            .command
+            .stdin(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|err| ErrProcess::spawn(command_line.clone(), err, started))?;
+        let stderr = child.stderr.take().map(read_in_background);
+        Ok(RunningProcess {
+            child,
+            command_line,
+            started,
+            stderr,
+        })
+    }
+}
+
+impl RunningProcess {
+    /// Entrada padrão do programa. Só pode ser pega uma vez.
+    pub fn stdin(&mut self) -> ChildStdin {
+        self.child.stdin.take().expect("stdin is piped")
+    }
+
+    /// Checa se o programa já terminou com erro, sem esperar por ele
+    pub fn has_failed(&mut self) -> bool {
+        matches!(self.child.try_wait(), Ok(Some(status)) if !status.success())
+    }
+
+    /// Interrompe o programa
+    pub fn kill(mut self) {
+        self.child.kill().ok();
+        self.child.wait().ok();
+    }
+
+    /// Espera o programa terminar. A entrada padrão já deve ter sido fechada.
+    pub fn wait(mut self) -> Result<ProcessOutput, ErrProcess> {
+        let status = self.child.wait();
+        let stderr = self
+            .stderr
+            .take()
+            .and_then(|reader| reader.join().ok())
+            .unwrap_or_default();
+        finish(self.command_line, status, Vec::new(), stderr, self.started)
+    }
+}
+
+/// Formata a linha de comando, pondo entre aspas os argumentos com espaços ou
+/// caracteres especiais
+pub fn command_line(command: &Command) -> String {
+    let quote = |raw: &OsStr| {
+        let raw = raw.to_string_lossy();
+        let plain = !raw.is_empty()
+            && raw
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "-_=.,:/@%+".contains(c));
+        match plain {
+            true => raw.into_owned(),
+            false => format!("'{}'", raw.replace('\'', r"'\''")),
+        }
+    };
+    std::iter::once(command.get_program())
+        .chain(command.get_args())
+        .map(quote)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// #######################
+//   Definições privadas
+// #######################
+impl Process {
+    fn is_retriable(&self, err: &ErrProcess) -> bool {
+        match &err.failure {
+            Failure::Spawn(_) => false,
+            Failure::Wait(_) => true,
+            Failure::Exit(status) => status
+                .code()
+                .is_none_or(|code| !self.permanent_codes.contains(&code)),
+        }
+    }
+
+    fn run_once(&mut self) -> Result<ProcessOutput, ErrProcess> {
+        let command_line = self.command_line();
+        let started = Instant::now();
+        self.command.stdout(Stdio::piped());
+        match self.interactive {
+            true => self
+                .command
+                .stdin(Stdio::inherit())
+                .stderr(Stdio::inherit()),
+            false => self.command.stdin(Stdio::null()).stderr(Stdio::piped()),
+        };
+        let mut child = self
+            .command
+            .spawn()
+            .map_err(|err| ErrProcess::spawn(command_line.clone(), err, started))?;
+
+        // NOTE: o stderr é lido em outra thread para que o programa não trave
+        // com o pipe cheio enquanto o stdout é lido
+        let stderr = child.stderr.take().map(read_in_background);
+        let mut stdout = Vec::new();
+        if let Some(mut out) = child.stdout.take() {
+            out.read_to_end(&mut stdout).ok();
+        }
+        let status = child.wait();
+        let stderr = stderr
+            .and_then(|reader| reader.join().ok())
+            .unwrap_or_default();
+        finish(command_line, status, stdout, stderr, started)
+    }
+}
+
+/// Monta o resultado de uma execução a partir do código de saída
+fn finish(
+    command_line: String,
+    status: io::Result<ExitStatus>,
+    stdout: Vec<u8>,
+    stderr: String,
+    started: Instant,
+) -> Result<ProcessOutput, ErrProcess> {
+    let duration = started.elapsed();
+    match status {
+        Ok(status) if status.success() => Ok(ProcessOutput { stdout }),
+        Ok(status) => Err(ErrProcess {
+            command_line,
+            failure: Failure::Exit(status),
+            stderr,
+            duration,
+            attempts: 1,
+        }),
+        Err(err) => Err(ErrProcess {
+            command_line,
+            failure: Failure::Wait(err),
+            stderr,
+            duration,
+            attempts: 1,
+        }),
+    }
+}
+
+fn read_in_background(mut stream: impl Read + Send + 'static) -> JoinHandle<String> {
+    thread::spawn(move || {
+        let mut content = Vec::new();
+        stream.read_to_end(&mut content).ok();
+        String::from_utf8_lossy(&content).trim().to_string()
+    })
+}
+
+/// Por que a execução falhou
+enum Failure {
+    /// O programa não pôde ser iniciado
+    Spawn(io::Error),
+    /// Não foi possível esperar o programa terminar
+    Wait(io::Error),
+    /// O programa terminou com erro
+    Exit(ExitStatus),
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Failure::Spawn(err) => write!(f, "unable to start: {}", err),
+            Failure::Wait(err) => write!(f, "unable to wait for it: {}", err),
+            Failure::Exit(status) => match status.code() {
+                Some(code) => write!(f, "exited with code {}", code),
+                None => write!(f, "terminated by a signal"),
+            },
+        }
+    }
+}
+
+// #######################
+//         Erros
+// #######################
+/// Erro lançado quando um programa externo falha. Carrega a linha de comando, o
+/// código de saída, o stderr capturado e a duração da execução.
+pub struct ErrProcess {
+    command_line: String,
+    failure: Failure,
+    stderr: String,
+    duration: Duration,
+    attempts: u32,
+}
+
+impl ErrProcess {
+    fn spawn(command_line: String, err: io::Error, started: Instant) -> Self {
+        Self {
+            command_line,
+            failure: Failure::Spawn(err),
+            stderr: String::new(),
+            duration: started.elapsed(),
+            attempts: 1,
+        }
+    }
+
+    /// Código de saída do programa, se ele chegou a terminar sozinho
+    pub fn code(&self) -> Option<i32> {
+        match &self.failure {
+            Failure::Exit(status) => status.code(),
+            _ => None,
+        }
+    }
+
+    /// Descrição da falha em uma linha, com a última linha do stderr
+    fn summary(&self) -> String {
+        let mut msg = format!(
+            "`{}` {} after {:.1}s",
+            self.command_line,
+            self.failure,
+            self.duration.as_secs_f64()
+        );
+        if let Some(line) = self.stderr.lines().last() {
+            msg.push_str(&format!(" ({})", line.trim()));
+        }
+        msg
+    }
+}
+
+impl BackyError for ErrProcess {
+    fn get_err_msg(&self) -> String {
+        let mut msg = format!(
+            "`{}` {} after {:.1}s",
+            self.command_line,
+            self.failure,
+            self.duration.as_secs_f64()
+        );
+        if self.attempts > 1 {
+            msg.push_str(&format!(" ({} attempts)", self.attempts));
+        }
+        msg.push('.');
+        if !self.stderr.is_empty() {
+            msg.push_str("\nstderr:");
+            for line in self.stderr.lines() {
+                msg.push_str(&format!("\n  {}", line));
+            }
+        }
+        msg
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(args: &[&str]) -> String {
+        let mut command = Command::new("rclone");
+        command.args(args);
+        command_line(&command)
+    }
+
+    #[test]
+    fn plain_arguments_are_not_quoted() {
+        assert_eq!(
+            line(&[
+                "copyto",
+                "/tmp/a-b_c.tar.gz",
+                "x:dir/file",
+                "--max-age=1d",
+                "user@host"
+            ]),
+            "rclone copyto /tmp/a-b_c.tar.gz x:dir/file --max-age=1d user@host"
+        );
+    }
+
+    #[test]
+    fn special_arguments_are_quoted() {
+        assert_eq!(line(&["with space"]), "rclone 'with space'");
+        assert_eq!(line(&[""]), "rclone ''");
+        assert_eq!(line(&["it's"]), r"rclone 'it'\''s'");
+        assert_eq!(
+            line(&["$HOME", "*.log", "a;b"]),
+            "rclone '$HOME' '*.log' 'a;b'"
+        );
+    }
+
+    #[test]
+    fn quoted_line_round_trips_through_the_shell() {
+        let args = [
+            "plain",
+            "two words",
+            "",
+            "it's",
+            "$HOME",
+            "`id`",
+            "a\\b",
+            "*",
+            "\"q\"",
+        ];
+        let mut command = Command::new("printf");
+        command.arg("%s\\n").args(args);
+        let output = Command::new("sh")
+            .arg("-c")
+            .arg(command_line(&command))
+            .output()
+            .unwrap();
+        let expected: String = args.iter().map(|arg| format!("{}\n", arg)).collect();
+        assert_eq!(String::from_utf8_lossy(&output.stdout), expected);
+    }
+}
@@ -0,0 +1,83 @@
+use crate::{
+    config::PolicyAction,
+    error::{BackyError, BackyResult},
+    logging::{log, warning},
+};
+
+// #######################
+//   Definições públicas
+// #######################
+/// Arquivos que não entraram completamente em um snapshot, seja ele criado
+/// pelo rsync ou pelo motor embutido
+#[derive(Debug, Default)]
+pub struct TransferIssues {
+    /// Arquivos que não puderam ser lidos ou copiados
+    pub partial: Vec<String>,
+    /// Arquivos que desapareceram da origem durante a transferência
+    pub vanished: Vec<String>,
+}
+
+/// Tipos de transferência incompleta que podem ser tratados pela política do
+/// usuário
+#[derive(Debug, Clone, Copy)]
+pub enum IssueKind {
+    Partial,
+    Vanished,
+}
+
+impl IssueKind {
+    fn describe(&self) -> &'static str {
+        match self {
+            IssueKind::Partial => "some files could not be transferred",
+            IssueKind::Vanished => "some files vanished before they could be transferred",
+        }
+    }
+}
+
+/// Aplica a ação escolhida pelo usuário para uma transferência incompleta do
+/// backup `name`, devolvendo um erro apenas quando a política assim mandar.
+pub fn apply_policy(
+    action: PolicyAction,
+    kind: IssueKind,
+    name: &str,
+    files: &[String],
+) -> BackyResult<()> {
+    match action {
+        PolicyAction::Error => Err(Box::new(ErrIncompleteTransfer {
+            name: name.to_string(),
+            kind,
+            files: files.to_vec(),
+        })),
+        PolicyAction::Warn => {
+            warning!("'{}' backup: {}:", name, kind.describe());
+            for file in files {
+                warning!("  {}", file);
+            }
+            Ok(())
+        }
+        PolicyAction::Ignore => Ok(()),
+    }
+}
+
+// #######################
+//         Erros
+// #######################
+/// Erro lançado quando um backup termina incompleto e a política do usuário
+/// trata esse caso como erro
+struct ErrIncompleteTransfer {
+    name: String,
+    kind: IssueKind,
+    files: Vec<String>,
+}
+impl BackyError for ErrIncompleteTransfer {
+    fn get_err_msg(&self) -> String {
+        let mut msg = format!("'{}' backup failed: {}", self.name, self.kind.describe());
+        if self.files.is_empty() {
+            msg.push_str(" (no file list available)");
+        }
+        for file in &self.files {
+            msg.push_str(&format!("\n  {}", file));
+        }
+        msg
+    }
+}
@@ -0,0 +1,254 @@
+use crate::{
+    checksum,
+    error::{BackyError, BackyResult},
+};
+
+use sha2::{Digest, Sha256};
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+// #######################
+//   Definições públicas
+// #######################
+/// Extensão do índice de um arquivo dividido em volumes
+pub const INDEX_EXTENSION: &str = "index";
+
+/// Um volume de um arquivo dividido, já gravado no disco
+pub struct Volume {
+    /// Nome do volume (`<arquivo>.001`, `<arquivo>.002`, ...)
+    pub name: String,
+    pub path: PathBuf,
+    pub size: u64,
+    /// SHA-256 do conteúdo, em hexadecimal
+    pub sha256: String,
+}
+
+/// Lê um índice gerado por [`VolumeWriter::finish`], devolvendo o nome e o
+/// SHA-256 de cada volume, na ordem
+pub fn parse_index(index: &str) -> Vec<(String, String)> {
+    index
+        .lines()
+        .filter_map(|line| line.split_once("  "))
+        .map(|(sha256, name)| (name.to_string(), sha256.to_string()))
+        .collect()
+}
+
+/// Divide o que é escrito nele em volumes de até `limit` bytes, gravados em
+/// `dir`. Cada volume completo é entregue a `on_volume` (que pode enviá-lo e
+/// apagá-lo) antes de o próximo ser criado, de forma que apenas um volume
+/// ocupa o disco por vez.
+pub struct VolumeWriter<F: FnMut(&Volume) -> BackyResult<()>> {
+    dir: PathBuf,
+    base: String,
+    limit: u64,
+    on_volume: F,
+    current: Option<OpenVolume>,
+    /// (nome, SHA-256) dos volumes já entregues
+    done: Vec<(String, String)>,
+    /// Erro de `on_volume`, que interrompe a escrita
+    failure: Option<Box<dyn BackyError>>,
+}
+
+impl<F: FnMut(&Volume) -> BackyResult<()>> VolumeWriter<F> {
+    pub fn new(dir: &Path, base: &str, limit: u64, on_volume: F) -> Self {
+        Self {
+            dir: dir.to_owned(),
+            base: base.to_string(),
+            limit: limit.max(1),
+            on_volume,
+            current: None,
+            done: Vec::new(),
+            failure: None,
+        }
+    }
+
+    /// Entrega o último volume e devolve o índice do arquivo, no formato do
+    /// `sha256sum` (os volumes podem ser conferidos com `sha256sum -c`)
+    pub fn finish(mut self) -> BackyResult<String> {
+        if self.current.is_none() && self.done.is_empty() {
+            self.open_next().map_err(|err| fail(&self.dir, err))?;
+        }
+        if let Err(err) = self.close_current() {
+            return Err(self.failure.take().unwrap_or_else(|| fail(&self.dir, err)));
+        }
+        Ok(self
+            .done
+            .iter()
+            .map(|(name, sha256)| format!("{}  {}\n", sha256, name))
+            .collect())
+    }
+}
+
+impl<F: FnMut(&Volume) -> BackyResult<()>> Write for VolumeWriter<F> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.current.is_none() {
+            self.open_next()?;
+        }
+        let volume = self.current.as_mut().expect("a volume is open");
+        let room = (self.limit - volume.size).min(buf.len() as u64) as usize;
+        volume.file.write_all(&buf[..room])?;
+        volume.hasher.update(&buf[..room]);
+        volume.size += room as u64;
+        if volume.size == self.limit {
+            self.close_current()?;
+        }
+        Ok(room)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.current {
+            Some(volume) => volume.file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+// #######################
+//   Definições privadas
+// #######################
+/// O volume sendo escrito
+struct OpenVolume {
+    name: String,
+    path: PathBuf,
+    file: BufWriter<File>,
+    hasher: Sha256,
+    size: u64,
+}
+
+impl<F: FnMut(&Volume) -> BackyResult<()>> VolumeWriter<F> {
+    fn open_next(&mut self) -> io::Result<()> {
+        let name = format!("{}.{:03}", self.base, self.done.len() + 1);
+        let path = self.dir.join(&name);
+        self.current = Some(OpenVolume {
+            file: BufWriter::new(File::create(&path)?),
+            name,
+            path,
+            hasher: Sha256::new(),
+            size: 0,
+        });
+        Ok(())
+    }
+
+    /// Fecha o volume atual e o entrega a `on_volume`
+    fn close_current(&mut self) -> io::Result<()> {
+        let Some(mut open) = self.current.take() else {
+            return Ok(());
+        };
+        open.file.flush()?;
+        let volume = Volume {
+            name: open.name,
+            path: open.path,
+            size: open.size,
+            sha256: checksum::hex(open.hasher),
+        };
+        if let Err(err) = (self.on_volume)(&volume) {
+            self.failure = Some(err);
+            return Err(io::Error::other(format!(
+                "unable to deliver volume '{}'",
+                volume.name
+            )));
+        }
+        self.done.push((volume.name, volume.sha256));
+        Ok(())
+    }
+}
+
+fn fail(path: &Path, err: io::Error) -> Box<dyn BackyError> {
+    Box::new(ErrVolume {
+        path: path.to_owned(),
+        err,
+    })
+}
+
+// #######################
+//         Erros
+// #######################
+/// Erro lançado quando não é possível gravar um volume
+struct ErrVolume {
+    path: PathBuf,
+    err: io::Error,
+}
+impl BackyError for ErrVolume {
+    fn get_err_msg(&self) -> String {
+        format!(
+            "unable to write volume in '{}':\n{}",
+            self.path.display(),
+            self.err
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Escreve `data` em volumes de `limit` bytes, devolvendo o índice e o
+    /// nome, o tamanho e o conteúdo de cada volume entregue
+    fn split(data: &[u8], limit: u64) -> (String, Vec<(String, u64, Vec<u8>)>) {
+        let dir = tempfile::tempdir().unwrap();
+        let mut delivered = Vec::new();
+        let mut writer = VolumeWriter::new(dir.path(), "file.tar", limit, |volume| {
+            let content = std::fs::read(&volume.path).unwrap();
+            assert_eq!(
+                volume.sha256,
+                checksum::hex(Sha256::new_with_prefix(&content))
+            );
+            delivered.push((volume.name.clone(), volume.size, content));
+            Ok(())
+        });
+        writer.write_all(data).unwrap();
+        let index = writer
+            .finish()
+            .unwrap_or_else(|err| panic!("{}", err.get_err_msg()));
+        (index, delivered)
+    }
+
+    #[test]
+    fn writes_across_volume_boundaries() {
+        let data: Vec<u8> = (0..25).collect();
+        let (index, volumes) = split(&data, 10);
+
+        let sizes: Vec<_> = volumes
+            .iter()
+            .map(|(name, size, _)| (name.as_str(), *size))
+            .collect();
+        assert_eq!(
+            sizes,
+            [
+                ("file.tar.001", 10),
+                ("file.tar.002", 10),
+                ("file.tar.003", 5)
+            ]
+        );
+        let joined: Vec<u8> = volumes.iter().flat_map(|(_, _, c)| c.clone()).collect();
+        assert_eq!(joined, data);
+
+        let entries = parse_index(&index);
+        assert_eq!(entries.len(), 3);
+        for ((name, sha256), (volume, _, content)) in entries.iter().zip(&volumes) {
+            assert_eq!(name, volume);
+            assert_eq!(sha256, &checksum::hex(Sha256::new_with_prefix(content)));
+        }
+    }
+
+    #[test]
+    fn exact_multiple_creates_no_empty_volume() {
+        let (_, volumes) = split(&[7; 20], 10);
+        let sizes: Vec<_> = volumes.iter().map(|(_, size, _)| *size).collect();
+        assert_eq!(sizes, [10, 10]);
+    }
+
+    #[test]
+    fn empty_input_creates_one_empty_volume() {
+        let (index, volumes) = split(&[], 10);
+        assert_eq!(volumes.len(), 1);
+        assert_eq!(volumes[0].1, 0);
+        assert_eq!(parse_index(&index).len(), 1);
+    }
+}